@@ -0,0 +1,16 @@
+// Generates the C header from src/capi.rs into OUT_DIR when building with
+// the capi feature. The copy in include/ is checked in, so C code can use
+// it without, and a test in src/capi.rs fails when the two differ.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = std::env::var("OUT_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        cbindgen::generate(&dir)
+            .expect("couldn't generate the C header")
+            .write_to_file(std::path::Path::new(&out).join("rsbefunge.h"));
+    }
+}
@@ -0,0 +1,82 @@
+// What the fuzz targets share: the programs they're given, and the limits
+// and sandboxing every run of one has.
+
+use arbitrary::Arbitrary;
+use rsbefunge::funge::{Cell, Dialect, Limits, Registry, VmBuilder, VmContext};
+use std::io::{self, Cursor};
+
+// no run goes on for longer than this, whatever the program does
+pub const TICKS: usize = 2_000;
+
+pub const LIMITS: Limits = Limits {
+    max_stack: Some(4096),
+    max_cells: Some(1 << 16),
+    max_output: Some(4096),
+    max_time: None, // would make runs depend on how busy the machine is
+};
+
+// A grid of code, one row per line, and whatever it reads from stdin
+#[derive(Debug, Arbitrary)]
+pub struct Program {
+    pub befunge93: bool,
+    pub seed: u64,
+    pub rows: Vec<Vec<u8>>,
+    pub input: Vec<u8>,
+}
+
+impl Program {
+    pub fn source(&self) -> Vec<u8> {
+        self.rows.join(&b'\n')
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        match self.befunge93 {
+            true => Dialect::Befunge93,
+            false => Dialect::Befunge98,
+        }
+    }
+
+    // a builder with everything set but the code
+    pub fn builder(&self) -> VmBuilder {
+        VmBuilder::new()
+            .dialect(self.dialect())
+            .input(Cursor::new(self.input.clone()))
+            .output(io::sink())
+            .seed(self.seed)
+            .limits(LIMITS)
+    }
+}
+
+// Stands in for `i` and `o` with instructions that take the same arguments
+// and then reflect, as if the file couldn't be opened. A fuzzed file name
+// can be any path at all, `/dev/zero` or one outside the base directory.
+pub fn sandbox<C: Cell>(registry: &mut Registry<C>, dialect: Dialect) {
+    if dialect == Dialect::Befunge93 {
+        return; // where `i` and `o` only ever reflect
+    }
+    registry
+        .register('i', |context| {
+            pop_string(context);
+            context.pop(); // flags
+            pop_vector(context); // where to load it
+            context.reflect();
+            Ok(())
+        })
+        .register('o', |context| {
+            pop_string(context);
+            context.pop(); // flags
+            pop_vector(context); // where to start
+            pop_vector(context); // how much to write
+            context.reflect();
+            Ok(())
+        });
+}
+
+fn pop_string<C: Cell>(context: &mut VmContext<C>) {
+    while !context.pop().is_zero() {}
+}
+
+fn pop_vector<C: Cell>(context: &mut VmContext<C>) {
+    context.pop();
+    context.pop();
+}
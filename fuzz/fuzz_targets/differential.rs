@@ -0,0 +1,99 @@
+#![no_main]
+//! Runs each program in usize, i64 and BigInt cells, which should agree on
+//! everything it does so long as no value it makes gets too wide for an
+//! i64. usize and i64 share a width but not a signedness, and BigInt cells
+//! take none of the fixed-width arithmetic, so each checks the others'.
+//!
+//! The interpreter decodes every instruction as it reaches it and has no
+//! compiled or cached execution path, so comparing against one is out of
+//! scope until it has.
+
+mod common;
+
+use common::{Program, TICKS};
+use libfuzzer_sys::fuzz_target;
+use num_bigint::BigInt;
+use rsbefunge::funge::{Cell, Direction, Location, Observer, Outcome, SharedBuffer, Step};
+
+// what a run left behind
+#[derive(Debug, PartialEq)]
+struct Trace {
+    outcome: Result<Outcome, String>,
+    output: Vec<u8>,
+    stack: Vec<i64>,
+    location: (i64, i64),
+    delta: Direction,
+}
+
+// whether any value pushed or written into funge space so far was wider
+// than an i64
+#[derive(Default)]
+struct TooWide(bool);
+
+impl Observer<BigInt> for TooWide {
+    fn push(&mut self, value: &BigInt) {
+        self.0 |= value.bits() > 63;
+    }
+
+    fn write(&mut self, _: &Location, value: &BigInt) {
+        self.0 |= value.bits() > 63;
+    }
+}
+
+// `None` when `too_wide` says the run went past what an i64 can hold
+fn trace<C: Cell, O: Observer<C>>(
+    program: &Program,
+    observer: O,
+    too_wide: impl Fn(&O) -> bool,
+) -> Option<Trace> {
+    let output = SharedBuffer::new();
+    let mut vm = program
+        .builder()
+        .source(program.source())
+        .output(output.clone())
+        .build_observed::<C, O>(observer);
+    common::sandbox(vm.registry_mut(), program.dialect());
+    // `y` pushes the date and time, which can change from one run to the next
+    vm.registry_mut().register('y', |context| {
+        context.reflect();
+        Ok(())
+    });
+
+    // stepped rather than run, to stop as soon as a value gets too wide
+    let mut outcome = Ok(Outcome::OutOfTicks { ticks: TICKS });
+    for ticks in 1..=TICKS {
+        let step = vm.step();
+        if too_wide(vm.observer()) {
+            return None;
+        }
+        match step {
+            Ok(Step::Running) => continue,
+            Ok(Step::Stopped) => {
+                outcome = Ok(match vm.exit_status() {
+                    Some(status) => Outcome::Quit { status, ticks },
+                    None => Outcome::Stopped { ticks },
+                })
+            }
+            Err(e) => outcome = Err(e.to_string()),
+        }
+        break;
+    }
+
+    let location = vm.get_location();
+    Some(Trace {
+        outcome,
+        output: output.take(),
+        stack: vm.get_stack().as_slice().iter().map(Cell::as_i64).collect(),
+        location: (location.0, location.1),
+        delta: vm.get_delta(),
+    })
+}
+
+fuzz_target!(|program: Program| {
+    let fixed = trace::<usize, _>(&program, (), |_| false);
+    assert_eq!(fixed, trace::<i64, _>(&program, (), |_| false));
+
+    if let Some(big) = trace::<BigInt, _>(&program, TooWide::default(), |o| o.0) {
+        assert_eq!(fixed, Some(big));
+    }
+});
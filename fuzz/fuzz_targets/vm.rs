@@ -0,0 +1,83 @@
+#![no_main]
+//! Runs arbitrary programs, on arbitrary input, in every kind of cell the
+//! interpreter has. Whatever a program does it should end in an `Outcome`
+//! or a `VmError`, never a panic.
+
+mod common;
+
+use arbitrary::Arbitrary;
+use common::{Program, TICKS};
+use libfuzzer_sys::fuzz_target;
+use num_bigint::BigInt;
+use rsbefunge::funge::{Location, Observer, Overflow, Step};
+
+#[derive(Debug, Arbitrary)]
+enum Cells {
+    Fixed,  // usize, as the CLI runs by default
+    Narrow, // i8, where overflowing is easy
+    Big,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Run {
+    program: Program,
+    cells: Cells,
+    overflow: u8,
+    detect_loops: bool,
+    coverage: bool,
+}
+
+// BigInt cells have no limit of their own, and `:*` squares one every two
+// ticks, so a run of them stops once any value grows wider than this
+const MAX_BITS: u64 = 1 << 12;
+
+// the widest value pushed or written into funge space so far
+#[derive(Default)]
+struct Widest(u64);
+
+impl Observer<BigInt> for Widest {
+    fn push(&mut self, value: &BigInt) {
+        self.0 = self.0.max(value.bits());
+    }
+
+    fn write(&mut self, _: &Location, value: &BigInt) {
+        self.0 = self.0.max(value.bits());
+    }
+}
+
+fuzz_target!(|run: Run| {
+    let dialect = run.program.dialect();
+    let builder = run
+        .program
+        .builder()
+        .source(run.program.source())
+        .overflow(match run.overflow % 3 {
+            0 => Overflow::Wrap,
+            1 => Overflow::Saturate,
+            _ => Overflow::Trap,
+        })
+        .detect_loops(run.detect_loops)
+        .coverage(run.coverage);
+
+    match run.cells {
+        Cells::Fixed => {
+            let mut vm = builder.build();
+            common::sandbox(vm.registry_mut(), dialect);
+            let _ = vm.run(TICKS);
+        }
+        Cells::Narrow => {
+            let mut vm = builder.build_with_cells::<i8>();
+            common::sandbox(vm.registry_mut(), dialect);
+            let _ = vm.run(TICKS);
+        }
+        Cells::Big => {
+            let mut vm = builder.build_observed::<BigInt, _>(Widest::default());
+            common::sandbox(vm.registry_mut(), dialect);
+            for _ in 0..TICKS {
+                if !matches!(vm.step(), Ok(Step::Running)) || vm.observer().0 > MAX_BITS {
+                    break;
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,159 @@
+/* Generated from src/capi.rs by cbindgen, don't edit */
+
+#ifndef RSBEFUNGE_H
+#define RSBEFUNGE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The way the IP is heading
+ */
+typedef enum FungeDirection {
+  FUNGE_DIRECTION_NORTH = 0,
+  FUNGE_DIRECTION_SOUTH = 1,
+  FUNGE_DIRECTION_EAST = 2,
+  FUNGE_DIRECTION_WEST = 3,
+} FungeDirection;
+
+/**
+ * How funge_vm_step and funge_vm_run went
+ */
+typedef enum FungeStatus {
+  FUNGE_STATUS_RUNNING = 0,
+  /**
+   * With `@` or `q`, see funge_vm_exit_status
+   */
+  FUNGE_STATUS_STOPPED = 1,
+  /**
+   * The tick limit handed to funge_vm_run was reached
+   */
+  FUNGE_STATUS_OUT_OF_TICKS = 2,
+  FUNGE_STATUS_STACK_OVERFLOW = 3,
+  FUNGE_STATUS_SPACE_FULL = 4,
+  FUNGE_STATUS_TOO_MUCH_OUTPUT = 5,
+  FUNGE_STATUS_TIMED_OUT = 6,
+  FUNGE_STATUS_LOOPING = 7,
+  /**
+   * A null pointer where one isn't allowed
+   */
+  FUNGE_STATUS_INVALID_ARGUMENT = 8,
+  FUNGE_STATUS_OVERFLOW = 9,
+} FungeStatus;
+
+/**
+ * A program and its callbacks, only ever handled through a pointer
+ */
+typedef struct FungeVm FungeVm;
+
+/**
+ * Settings for funge_vm_new, a null pointer means all of them are 0.
+ * Limits of 0 are unlimited, but for max_cells, where 0 is the default of
+ * 4194304 cells that aren't spaces.
+ */
+typedef struct FungeOptions {
+  bool befunge93;
+  /**
+   * Seed `?` with `seed`, rather than at random
+   */
+  bool seeded;
+  uint64_t seed;
+  size_t max_stack;
+  size_t max_cells;
+  size_t max_output;
+  uint64_t max_time_ms;
+} FungeOptions;
+
+/**
+ * Writes `len` bytes from `buf`, returning how many it wrote, at most `len`,
+ * or a negative number on error, which makes the instruction writing reflect
+ */
+typedef ptrdiff_t (*FungeWriteFn)(void *user_data, const uint8_t *buf, size_t len);
+
+/**
+ * Reads up to `len` bytes into `buf`, returning how many it read, 0 at end
+ * of input or a negative number on error
+ */
+typedef ptrdiff_t (*FungeReadFn)(void *user_data, uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a VM running the `len` bytes at `source`, or returns null if
+ * `source` is null. `options` may be null.
+ */
+struct FungeVm *funge_vm_new(const uint8_t *source, size_t len, const struct FungeOptions *options);
+
+/**
+ * Frees a VM made by funge_vm_new, doing nothing if `vm` is null
+ */
+void funge_vm_free(struct FungeVm *vm);
+
+/**
+ * Sends output to `write`, or drops it if `write` is null
+ */
+void funge_vm_set_output(struct FungeVm *vm, FungeWriteFn write, void *user_data);
+
+/**
+ * Reads input from `read`, or ends input if `read` is null
+ */
+void funge_vm_set_input(struct FungeVm *vm, FungeReadFn read, void *user_data);
+
+/**
+ * Runs one instruction, returning FUNGE_STATUS_RUNNING, _STOPPED or an
+ * error
+ */
+enum FungeStatus funge_vm_step(struct FungeVm *vm);
+
+/**
+ * Runs until the program stops or `max_ticks` have run (0 for no limit),
+ * storing how many ran in `ticks` unless it's null
+ */
+enum FungeStatus funge_vm_run(struct FungeVm *vm, size_t max_ticks, size_t *ticks);
+
+/**
+ * What went wrong, for the last error status funge_vm_step or funge_vm_run
+ * returned, or null. Valid until the next call with this VM.
+ */
+const char *funge_vm_last_error(const struct FungeVm *vm);
+
+/**
+ * How deep the stack is
+ */
+size_t funge_vm_stack_len(const struct FungeVm *vm);
+
+/**
+ * Copies up to `len` cells from the top of the stack down into `cells`,
+ * returning how many it copied
+ */
+size_t funge_vm_stack(const struct FungeVm *vm, int64_t *cells, size_t len);
+
+/**
+ * The cell at (x, y) in funge space
+ */
+int64_t funge_vm_get(const struct FungeVm *vm, int64_t x, int64_t y);
+
+/**
+ * Where the IP is, storing whichever of `x` and `y` aren't null
+ */
+void funge_vm_location(const struct FungeVm *vm, int64_t *x, int64_t *y);
+
+/**
+ * The way the IP is heading, east for a null `vm`
+ */
+enum FungeDirection funge_vm_delta(const struct FungeVm *vm);
+
+/**
+ * Whether the program quit with `q`, storing the status it popped in
+ * `status` unless that's null
+ */
+bool funge_vm_exit_status(const struct FungeVm *vm, int32_t *status);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RSBEFUNGE_H */
@@ -0,0 +1,197 @@
+//! `befunge!`, for embedding Befunge programs in Rust.
+//!
+//! The program is laid out in funge space and linted at compile time, so
+//! that a program with unreachable code, unknown instructions or a string
+//! running off the edge of the program doesn't compile. The error points
+//! at the cell, as well as the compiler can, and gives its row and column.
+//! Popping an empty stack and never stopping are left alone, as they're
+//! often meant.
+//!
+//! The macro expands to a [`rsbefunge::funge::VmBuilder`] with the program
+//! already loaded, ready for input and output to be set before `build`:
+//!
+//! ```ignore
+//! use rsbefunge_macros::befunge;
+//!
+//! let mut vm = befunge!(r#"> "!olleH" ,,,,,, @"#).output(Vec::new()).build();
+//! ```
+//!
+//! The program is Funge-98 unless it starts with `befunge93,`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use quote::quote;
+use rsbefunge::funge::{Dialect, Location, Space};
+use rsbefunge::lint::{self, Kind};
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+#[proc_macro]
+pub fn befunge(input: TokenStream) -> TokenStream {
+    // errors still expand to a builder, so that only they get reported
+    expand(input.into())
+        .unwrap_or_else(|err| {
+            let errors = err.to_compile_error();
+            quote!({
+                #errors
+                ::rsbefunge::funge::VmBuilder::new()
+            })
+        })
+        .into()
+}
+
+struct Input {
+    dialect: Dialect,
+    program: LitStr,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut dialect = Dialect::Befunge98;
+        if input.peek(Ident) {
+            let name: Ident = input.parse()?;
+            dialect = match name.to_string().as_str() {
+                "befunge93" => Dialect::Befunge93,
+                "befunge98" => Dialect::Befunge98,
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "expected befunge93 or befunge98",
+                    ))
+                }
+            };
+            input.parse::<Token![,]>()?;
+        }
+        let program = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(Input { dialect, program })
+    }
+}
+
+fn expand(input: TokenStream2) -> syn::Result<TokenStream2> {
+    let Input { dialect, program } = syn::parse2(input)?;
+    let code = program.value();
+
+    let mut errors: Option<syn::Error> = None;
+    for finding in lint::lint(code.as_bytes(), dialect) {
+        if matches!(finding.kind, Kind::StackUnderflow | Kind::NoExit) {
+            continue;
+        }
+        let span = cell_span(&program, &code, finding.x, finding.y, finding.len);
+        let error = syn::Error::new(span, format!("befunge {}", finding));
+        match errors.as_mut() {
+            Some(errors) => errors.combine(error),
+            None => errors = Some(error),
+        }
+    }
+    if let Some(errors) = errors {
+        return Err(errors);
+    }
+
+    let space = Space::<usize>::new(code.as_bytes());
+    let (w, h) = space.dims();
+    let rows = (0..h).map(|y| {
+        let row: Vec<u8> = (0..w)
+            .map(|x| space.get(&Location(x as i64, y as i64)) as u8)
+            .collect();
+        Literal::byte_string(&row)
+    });
+    let dialect = match dialect {
+        Dialect::Befunge93 => quote!(::rsbefunge::funge::Dialect::Befunge93),
+        Dialect::Befunge98 => quote!(::rsbefunge::funge::Dialect::Befunge98),
+    };
+
+    Ok(quote! {
+        ::rsbefunge::funge::VmBuilder::new()
+            .dialect(#dialect)
+            .space(::rsbefunge::funge::Space::from_rows(&[#(&#rows[..]),*]))
+    })
+}
+
+// The span of `len` cells from (x, y) in the literal. Only the compiler's
+// nightly builds can point inside a literal, and only when the source text
+// matches the program byte for byte, so anywhere else this is the whole
+// literal.
+fn cell_span(program: &LitStr, code: &str, x: usize, y: usize, len: usize) -> Span {
+    let token = program.token();
+    let text = token.to_string();
+    let prefix = match text.starts_with('r') {
+        true => text.find('"').map(|i| i + 1),
+        false if !text.contains('\\') => Some(1),
+        false => None,
+    };
+    if code.contains('\x0c') {
+        return program.span();
+    }
+
+    let start = prefix.and_then(|prefix| Some(prefix + line_start(code, y)? + x));
+    start
+        .and_then(|start| token.subspan(start..start + len))
+        .unwrap_or_else(|| program.span())
+}
+
+// the byte offset of row `y`, splitting lines the way funge space does
+fn line_start(code: &str, y: usize) -> Option<usize> {
+    let bytes = code.as_bytes();
+    let mut row = 0;
+    let mut i = 0;
+    while row < y {
+        match bytes.get(i)? {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                i += 2;
+                row += 1;
+            }
+            b'\r' | b'\n' => {
+                i += 1;
+                row += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    Some(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(input: TokenStream2) -> Vec<String> {
+        match expand(input) {
+            Ok(_) => Vec::new(),
+            Err(err) => err.into_iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn rejects_unreachable_and_unknown_cells() {
+        assert_eq!(
+            messages(quote!("1.@ 9\n>Z<")),
+            vec![
+                "befunge 1:5: unreachable cell",
+                "befunge 2:1: 3 unreachable cells",
+            ]
+        );
+        assert_eq!(
+            messages(quote!(befunge93, "1.{@")),
+            vec!["befunge 1:3: unknown instruction '{'"]
+        );
+    }
+
+    #[test]
+    fn accepts_programs_that_underflow_or_never_stop() {
+        assert!(messages(quote!(".@")).is_empty());
+        assert!(messages(quote!(
+            r#">1.v
+^  <"#
+        ))
+        .is_empty());
+    }
+
+    #[test]
+    fn rows_start_after_any_line_ending() {
+        assert_eq!(line_start("ab\r\ncd\ref\ngh", 0), Some(0));
+        assert_eq!(line_start("ab\r\ncd\ref\ngh", 2), Some(7));
+        assert_eq!(line_start("ab\r\ncd\ref\ngh", 3), Some(10));
+        assert_eq!(line_start("ab", 2), None);
+    }
+}
@@ -0,0 +1,28 @@
+// Programs embedded with `befunge!` run like any other
+
+use rsbefunge::funge::{Outcome, SharedBuffer};
+use rsbefunge_macros::befunge;
+
+#[test]
+fn runs_an_embedded_program() {
+    let output = SharedBuffer::new();
+    let mut vm = befunge!(r#"> "!olleH" ,,,,,, @"#)
+        .output(output.clone())
+        .build();
+    assert!(matches!(vm.run(1000), Ok(Outcome::Stopped { .. })));
+    assert_eq!(output.contents(), b"Hello!");
+}
+
+#[test]
+fn keeps_the_layout_and_dialect() {
+    let output = SharedBuffer::new();
+    let mut vm = befunge!(
+        befunge93, "v
+>9.@"
+    )
+    .output(output.clone())
+    .build();
+    assert!(matches!(vm.run(1000), Ok(Outcome::Stopped { .. })));
+    assert_eq!(vm.space.dims(), (4, 2));
+    assert_eq!(output.contents(), b"9 ");
+}
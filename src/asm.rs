@@ -0,0 +1,816 @@
+//! Lays out a linear stack assembly as a Funge-98 program.
+//!
+//! Writing 2D code by hand is slow. [`parse`] reads an assembly with one
+//! statement per line, `;` starting a comment, and [`assemble`] lays it
+//! out. The statements are:
+//!
+//! | statement | does |
+//! |---|---|
+//! | `push 42`, `push -7`, `push 'x'` | pushes a number or a character |
+//! | `push "text"` | pushes each character in turn, the last on top |
+//! | `add` `sub` `mul` `div` `mod` `not` `gt` | arithmetic, as `+ - * / % ! \`` |
+//! | `dup` `drop` `swap` | as `:` `$` `\` |
+//! | `print` `printc` | as `.` and `,` |
+//! | `read` `readc` | as `&` and `~` |
+//! | `name:` | a label |
+//! | `jmp name` | jumps to a label |
+//! | `jz name`, `jnz name` | pops and jumps if it's zero, or isn't |
+//! | `call name`, `ret` | calls a routine and returns from it |
+//! | `halt` | stops, as does running off the end |
+//!
+//! Each basic block gets four rows. The top row is a corridor running
+//! west into column 0, where the IP turns down into the block's code,
+//! which runs east along the second row. Blocks end by stopping, by
+//! dropping straight into the next block's corridor, or by running east
+//! into a lane, a column to the right of all the code that carries the IP
+//! up or down to the corridor of the block it jumps to. Conditional jumps
+//! split on a `_` on the third row, the nonzero way doubling back onto the
+//! fourth. `call` keeps return addresses in funge space, in the rows just
+//! below the program, and `ret` finds its way back through a chain of
+//! blocks comparing against each place a call returns to.
+//!
+//! [`verify`] checks a layout by running it in a [`crate::funge::Vm`] and comparing what
+//! it prints with a straightforward interpretation of the assembly, and
+//! the [`SourceMap`] says which cells each line of assembly became.
+
+use crate::encode;
+use crate::funge::{Dialect, Outcome, SharedBuffer, VmBuilder};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Cursor;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op {
+    Push(i64),
+    PushString(Vec<u8>),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Not,
+    Gt,
+    Dup,
+    Drop,
+    Swap,
+    Print,
+    PrintChar,
+    Read,
+    ReadChar,
+    Halt,
+    Jump(String),
+    JumpZero(String),
+    JumpNonZero(String),
+    Call(String),
+    Ret,
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    op: Op,
+    line: usize,
+}
+
+// A parsed assembly program
+#[derive(Debug, Clone)]
+pub struct Program {
+    statements: Vec<Statement>,
+    labels: HashMap<String, usize>, // the statement each label is on
+}
+
+// A problem with the assembly on a line, counting from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+// The cells one line of assembly was laid out in, as (x, y)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mapping {
+    pub line: usize,
+    pub cells: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SourceMap {
+    pub lines: Vec<Mapping>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub code: String,
+    pub map: SourceMap,
+}
+
+pub fn parse(source: &str) -> Result<Program, Error> {
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message: String| Error { line, message };
+        let text = strip_comment(text).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(label) = text.strip_suffix(':') {
+            if !is_label(label) {
+                return Err(error(format!("{:?} isn't a valid label", label)));
+            }
+            if labels.insert(label.to_string(), statements.len()).is_some() {
+                return Err(error(format!("label {} is defined twice", label)));
+            }
+            continue;
+        }
+
+        let (mnemonic, operand) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, Some(operand.trim())),
+            None => (text, None),
+        };
+        let op = match (mnemonic, operand) {
+            ("push", Some(operand)) => parse_literal(operand).map_err(error)?,
+            ("jmp", Some(label)) => Op::Jump(label.to_string()),
+            ("jz", Some(label)) => Op::JumpZero(label.to_string()),
+            ("jnz", Some(label)) => Op::JumpNonZero(label.to_string()),
+            ("call", Some(label)) => Op::Call(label.to_string()),
+            ("push" | "jmp" | "jz" | "jnz" | "call", None) => {
+                return Err(error(format!("{} needs an operand", mnemonic)))
+            }
+            (_, Some(_)) => return Err(error(format!("{} takes no operand", mnemonic))),
+            (mnemonic, None) => match mnemonic {
+                "add" => Op::Add,
+                "sub" => Op::Sub,
+                "mul" => Op::Mul,
+                "div" => Op::Div,
+                "mod" => Op::Mod,
+                "not" => Op::Not,
+                "gt" => Op::Gt,
+                "dup" => Op::Dup,
+                "drop" => Op::Drop,
+                "swap" => Op::Swap,
+                "print" => Op::Print,
+                "printc" => Op::PrintChar,
+                "read" => Op::Read,
+                "readc" => Op::ReadChar,
+                "halt" => Op::Halt,
+                "ret" => Op::Ret,
+                _ => return Err(error(format!("unknown instruction {}", mnemonic))),
+            },
+        };
+        statements.push(Statement { op, line });
+    }
+
+    for statement in statements.iter() {
+        if let Op::Jump(label) | Op::JumpZero(label) | Op::JumpNonZero(label) | Op::Call(label) =
+            &statement.op
+        {
+            if !labels.contains_key(label) {
+                return Err(Error {
+                    line: statement.line,
+                    message: format!("no label called {}", label),
+                });
+            }
+        }
+    }
+    if let Some(ret) = statements.iter().find(|s| s.op == Op::Ret) {
+        if !statements.iter().any(|s| matches!(s.op, Op::Call(_))) {
+            return Err(Error {
+                line: ret.line,
+                message: "ret, but nothing is ever called".to_string(),
+            });
+        }
+    }
+
+    Ok(Program { statements, labels })
+}
+
+// `;` starts a comment, unless it's quoted
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    text
+}
+
+fn is_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_literal(operand: &str) -> Result<Op, String> {
+    let quoted = |quote: char| {
+        operand
+            .strip_prefix(quote)
+            .and_then(|s| s.strip_suffix(quote))
+            .filter(|s| s.is_ascii())
+    };
+
+    if let Some(text) = quoted('"').filter(|_| operand.len() >= 2) {
+        return Ok(Op::PushString(text.as_bytes().to_vec()));
+    }
+    if let Some(c) = quoted('\'').filter(|s| s.len() == 1) {
+        return Ok(Op::Push(c.as_bytes()[0] as i64));
+    }
+    operand
+        .parse()
+        .map(Op::Push)
+        .map_err(|_| format!("{} isn't a number, character or string", operand))
+}
+
+fn encode_number(n: i64) -> Vec<u8> {
+    encode::number(n, Dialect::Befunge98).into_bytes()
+}
+
+fn encode_string(text: &[u8]) -> Vec<u8> {
+    encode::string(text, Dialect::Befunge98).into_bytes()
+}
+
+#[derive(Debug, Clone)]
+enum Exit {
+    Halt,
+    Jump(String),
+    Branch { zero: String, nonzero: String },
+}
+
+// a straight run of code, each piece with the line it came from
+#[derive(Debug, Clone)]
+struct Block {
+    label: String,
+    code: Vec<(Vec<u8>, usize)>,
+    exit: Exit,
+    exit_line: usize,
+}
+
+// labels of the blocks made up by the assembler, which can't clash with
+// the program's own
+fn returned(call: usize) -> String {
+    format!("call:{}", call)
+}
+
+fn dispatch(call: usize) -> String {
+    format!("ret:{}", call)
+}
+
+fn landing(call: usize) -> String {
+    format!("ret:{}:land", call)
+}
+
+fn block_label(index: usize) -> String {
+    format!("block:{}", index)
+}
+
+// Splits the program into blocks. The stack pointer for calls is kept at
+// (0, `data`), and return addresses along the row below.
+fn blocks(program: &Program, data: usize) -> Vec<Block> {
+    let number = |n: usize| encode_number(n as i64);
+    let mut blocks = Vec::new();
+    let mut code: Vec<(Vec<u8>, usize)> = Vec::new();
+    let mut label = block_label(0);
+    let mut calls = 0;
+
+    // labels on the same statement all name the first of them
+    let mut labels_at: HashMap<usize, &String> = HashMap::new();
+    for (name, at) in program.labels.iter() {
+        let first = labels_at.entry(*at).or_insert(name);
+        if name < *first {
+            *first = name;
+        }
+    }
+    let canonical = |name: &String| labels_at[&program.labels[name]].clone();
+
+    let uses_calls = program
+        .statements
+        .iter()
+        .any(|s| matches!(s.op, Op::Call(_)));
+    if uses_calls {
+        // the stack pointer starts at 0
+        let init = [b"0".to_vec(), b"0".to_vec(), number(data), b"p".to_vec()].concat();
+        code.push((init, 0));
+    }
+
+    for (i, statement) in program.statements.iter().enumerate() {
+        let line = statement.line;
+
+        // a label starts a new block, which the last one falls into
+        if let Some(name) = labels_at.get(&i) {
+            let name = name.to_string();
+            if i > 0 || !code.is_empty() {
+                let fall = Exit::Jump(name.clone());
+                end(&mut blocks, &mut code, &mut label, fall, 0, name);
+            } else {
+                label = name;
+            }
+        }
+
+        let next = block_label(blocks.len() + 1);
+        let simple = |cells: &[u8]| (cells.to_vec(), line);
+        match &statement.op {
+            Op::Push(n) => code.push((encode_number(*n), line)),
+            Op::PushString(text) => code.push((encode_string(text), line)),
+            Op::Add => code.push(simple(b"+")),
+            Op::Sub => code.push(simple(b"-")),
+            Op::Mul => code.push(simple(b"*")),
+            Op::Div => code.push(simple(b"/")),
+            Op::Mod => code.push(simple(b"%")),
+            Op::Not => code.push(simple(b"!")),
+            Op::Gt => code.push(simple(b"`")),
+            Op::Dup => code.push(simple(b":")),
+            Op::Drop => code.push(simple(b"$")),
+            Op::Swap => code.push(simple(b"\\")),
+            Op::Print => code.push(simple(b".")),
+            Op::PrintChar => code.push(simple(b",")),
+            Op::Read => code.push(simple(b"&")),
+            Op::ReadChar => code.push(simple(b"~")),
+            Op::Halt => end(&mut blocks, &mut code, &mut label, Exit::Halt, line, next),
+            Op::Jump(to) => {
+                let exit = Exit::Jump(canonical(to));
+                end(&mut blocks, &mut code, &mut label, exit, line, next)
+            }
+            Op::JumpZero(to) | Op::JumpNonZero(to) => {
+                let (zero, nonzero) = match &statement.op {
+                    Op::JumpZero(_) => (canonical(to), next.clone()),
+                    _ => (next.clone(), canonical(to)),
+                };
+                let exit = Exit::Branch { zero, nonzero };
+                end(&mut blocks, &mut code, &mut label, exit, line, next)
+            }
+            Op::Call(to) => {
+                // push the return address: sp g : call \ data+1 p
+                // then bump the stack pointer: 1+ 0 data p
+                let push = [
+                    b"0".to_vec(),
+                    number(data),
+                    b"g:".to_vec(),
+                    number(calls),
+                    b"\\".to_vec(),
+                    number(data + 1),
+                    b"p1+0".to_vec(),
+                    number(data),
+                    b"p".to_vec(),
+                ];
+                code.push((push.concat(), line));
+                let exit = Exit::Jump(canonical(to));
+                end(
+                    &mut blocks,
+                    &mut code,
+                    &mut label,
+                    exit,
+                    line,
+                    returned(calls),
+                );
+                calls += 1;
+            }
+            Op::Ret => {
+                // pop the return address: 0 data g 1- : 0 data p data+1 g
+                let pop = [
+                    b"0".to_vec(),
+                    number(data),
+                    b"g1-:0".to_vec(),
+                    number(data),
+                    b"p".to_vec(),
+                    number(data + 1),
+                    b"g".to_vec(),
+                ];
+                code.push((pop.concat(), line));
+                let exit = Exit::Jump(dispatch(0));
+                end(&mut blocks, &mut code, &mut label, exit, line, next)
+            }
+        }
+    }
+    // running off the end stops, including from labels after the last
+    // statement
+    let line = program.statements.last().map_or(0, |s| s.line);
+    if let Some(name) = labels_at.get(&program.statements.len()) {
+        let fall = Exit::Jump(name.to_string());
+        end(
+            &mut blocks,
+            &mut code,
+            &mut label,
+            fall,
+            0,
+            name.to_string(),
+        );
+    }
+    end(
+        &mut blocks,
+        &mut code,
+        &mut label,
+        Exit::Halt,
+        line,
+        String::new(),
+    );
+
+    // each return address is compared in turn, the last needs no check
+    for call in 0..calls {
+        let to_caller = Exit::Jump(returned(call));
+        match call + 1 < calls {
+            true => {
+                let compare = [b":".to_vec(), number(call), b"-".to_vec()].concat();
+                blocks.push(Block {
+                    label: dispatch(call),
+                    code: vec![(compare, 0)],
+                    exit: Exit::Branch {
+                        zero: landing(call),
+                        nonzero: dispatch(call + 1),
+                    },
+                    exit_line: 0,
+                });
+                blocks.push(Block {
+                    label: landing(call),
+                    code: vec![(b"$".to_vec(), 0)],
+                    exit: to_caller,
+                    exit_line: 0,
+                });
+            }
+            false => blocks.push(Block {
+                label: dispatch(call),
+                code: vec![(b"$".to_vec(), 0)],
+                exit: to_caller,
+                exit_line: 0,
+            }),
+        }
+    }
+    blocks
+}
+
+// finishes the block being built and starts the next, called `next`
+fn end(
+    blocks: &mut Vec<Block>,
+    code: &mut Vec<(Vec<u8>, usize)>,
+    label: &mut String,
+    exit: Exit,
+    exit_line: usize,
+    next: String,
+) {
+    blocks.push(Block {
+        label: std::mem::replace(label, next),
+        code: std::mem::take(code),
+        exit,
+        exit_line,
+    });
+}
+
+// funge space being laid out, with the lines each cell came from
+struct Grid {
+    rows: Vec<Vec<u8>>,
+    map: BTreeMap<usize, Vec<(usize, usize)>>,
+}
+
+impl Grid {
+    fn set(&mut self, x: usize, y: usize, cell: u8, line: usize) {
+        if self.rows.len() <= y {
+            self.rows.resize(y + 1, Vec::new());
+        }
+        if self.rows[y].len() <= x {
+            self.rows[y].resize(x + 1, b' ');
+        }
+        self.rows[y][x] = cell;
+        if line > 0 {
+            self.map.entry(line).or_default().push((x, y));
+        }
+    }
+}
+
+pub fn assemble(program: &Program) -> Assembly {
+    // the block count doesn't depend on where the call stack goes
+    let count = blocks(program, 0).len();
+    let blocks = blocks(program, count * 4);
+    let index: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.label.as_str(), i))
+        .collect();
+
+    let mut grid = Grid {
+        rows: Vec::new(),
+        map: BTreeMap::new(),
+    };
+    let mut ends = Vec::new();
+    for (b, block) in blocks.iter().enumerate() {
+        let (corridor, row) = (4 * b, 4 * b + 1);
+        grid.set(0, corridor, b'v', 0);
+        grid.set(0, row, b'>', 0);
+
+        let mut x = 1;
+        for (cells, line) in block.code.iter() {
+            for cell in cells.iter() {
+                grid.set(x, row, *cell, *line);
+                x += 1;
+            }
+        }
+        ends.push(x);
+    }
+
+    // lanes start to the right of everything, branches take two columns
+    let mut lane = ends.iter().map(|x| x + 1).max().unwrap_or(1);
+    let mut route = |grid: &mut Grid, from: usize, to: usize, line: usize| {
+        let corridor = 4 * to;
+        let turn = match corridor > from {
+            true => b'v',
+            false => b'^',
+        };
+        grid.set(lane, from, turn, line);
+        grid.set(lane, corridor, b'<', line);
+        lane += 1;
+    };
+
+    for (b, block) in blocks.iter().enumerate() {
+        let (row, x, line) = (4 * b + 1, ends[b], block.exit_line);
+        match &block.exit {
+            Exit::Halt => grid.set(x, row, b'@', line),
+            Exit::Jump(to) if index[to.as_str()] == b + 1 => {
+                grid.set(x, row, b'v', line);
+                grid.set(x, row + 3, b'<', line);
+            }
+            Exit::Jump(to) => route(&mut grid, row, index[to.as_str()], line),
+            Exit::Branch { zero, nonzero } => {
+                grid.set(x, row, b'v', line);
+                grid.set(x, row + 1, b'_', line);
+                grid.set(x - 1, row + 1, b'v', line);
+                grid.set(x - 1, row + 2, b'>', line);
+                route(&mut grid, row + 1, index[zero.as_str()], line);
+                route(&mut grid, row + 2, index[nonzero.as_str()], line);
+            }
+        }
+    }
+
+    let mut code = String::new();
+    for row in grid.rows.iter() {
+        code.push_str(String::from_utf8_lossy(row).trim_end());
+        code.push('\n');
+    }
+    let lines = grid
+        .map
+        .into_iter()
+        .map(|(line, mut cells)| {
+            cells.sort_by_key(|(x, y)| (*y, *x));
+            Mapping { line, cells }
+        })
+        .collect();
+
+    Assembly {
+        code,
+        map: SourceMap { lines },
+    }
+}
+
+// what the assembly does, run directly: its output, or an error if it
+// reads past the end of its input, returns with nowhere to go or runs for
+// longer than `max_steps`
+fn interpret(program: &Program, input: &[u8], max_steps: usize) -> Result<Vec<u8>, String> {
+    let mut stack: Vec<i64> = Vec::new();
+    let mut calls = Vec::new();
+    let mut output = Vec::new();
+    let mut input = input.iter().copied().peekable();
+    let mut pc = 0;
+    let pop = |stack: &mut Vec<i64>| stack.pop().unwrap_or(0);
+
+    for _ in 0..max_steps {
+        let statement = match program.statements.get(pc) {
+            Some(statement) => statement,
+            None => return Ok(output),
+        };
+        pc += 1;
+
+        let binary = |stack: &mut Vec<i64>, f: fn(i64, i64) -> i64| {
+            let (b, a) = (pop(stack), pop(stack));
+            stack.push(f(a, b));
+        };
+        match &statement.op {
+            Op::Push(n) => stack.push(*n),
+            Op::PushString(text) => stack.extend(text.iter().map(|b| *b as i64)),
+            Op::Add => binary(&mut stack, i64::wrapping_add),
+            Op::Sub => binary(&mut stack, i64::wrapping_sub),
+            Op::Mul => binary(&mut stack, i64::wrapping_mul),
+            Op::Div => binary(&mut stack, |a, b| match b {
+                0 => 0,
+                _ => a.wrapping_div(b),
+            }),
+            Op::Mod => binary(&mut stack, |a, b| match b {
+                0 => 0,
+                _ => a.wrapping_rem(b),
+            }),
+            Op::Gt => binary(&mut stack, |a, b| (a > b) as i64),
+            Op::Not => {
+                let a = pop(&mut stack);
+                stack.push((a == 0) as i64);
+            }
+            Op::Dup => {
+                let a = pop(&mut stack);
+                stack.extend([a, a]);
+            }
+            Op::Drop => {
+                pop(&mut stack);
+            }
+            Op::Swap => {
+                let (b, a) = (pop(&mut stack), pop(&mut stack));
+                stack.extend([b, a]);
+            }
+            Op::Print => output.extend(format!("{} ", pop(&mut stack)).bytes()),
+            Op::PrintChar => output.push(pop(&mut stack) as u8),
+            Op::Read => {
+                while input.peek().is_some_and(|b| !b.is_ascii_digit()) {
+                    input.next();
+                }
+                if input.peek().is_none() {
+                    return Err("it reads past the end of its input".to_string());
+                }
+                let mut value: i64 = 0;
+                while let Some(b) = input.next_if(|b| b.is_ascii_digit()) {
+                    value = value.saturating_mul(10).saturating_add((b - b'0') as i64);
+                }
+                stack.push(value);
+            }
+            Op::ReadChar => match input.next() {
+                Some(b) => stack.push(b as i64),
+                None => return Err("it reads past the end of its input".to_string()),
+            },
+            Op::Halt => return Ok(output),
+            Op::Jump(label) => pc = program.labels[label],
+            Op::JumpZero(label) if pop(&mut stack) == 0 => pc = program.labels[label],
+            Op::JumpNonZero(label) if pop(&mut stack) != 0 => pc = program.labels[label],
+            Op::JumpZero(_) | Op::JumpNonZero(_) => (),
+            Op::Call(label) => {
+                calls.push(pc);
+                pc = program.labels[label];
+            }
+            Op::Ret => match calls.pop() {
+                Some(to) => pc = to,
+                None => {
+                    return Err(format!(
+                        "line {} returns with nowhere to go",
+                        statement.line
+                    ))
+                }
+            },
+        }
+    }
+    Err(format!("it runs for more than {} steps", max_steps))
+}
+
+// Runs the assembled program in a VM and checks that it prints the same
+// as the assembly does
+pub fn verify(
+    program: &Program,
+    assembly: &Assembly,
+    input: &[u8],
+    max_steps: usize,
+) -> Result<(), String> {
+    let expected =
+        interpret(program, input, max_steps).map_err(|err| format!("can't verify, {}", err))?;
+
+    let output = SharedBuffer::new();
+    let mut vm = VmBuilder::new()
+        .source(assembly.code.as_bytes())
+        .input(Cursor::new(input.to_vec()))
+        .output(output.clone())
+        .build();
+    // routing takes a few ticks per step, but never more than crossing the
+    // grid twice
+    let (w, h) = vm.space.dims();
+    let limit = max_steps.saturating_mul(2 * (w + h) + 64);
+    match vm.run(limit) {
+        Ok(Outcome::Stopped { .. }) => (),
+        Ok(outcome) => return Err(format!("the layout didn't stop: {:?}", outcome)),
+        Err(err) => return Err(format!("the layout failed: {}", err)),
+    }
+
+    let actual = output.contents();
+    match actual == expected {
+        true => Ok(()),
+        false => Err(format!(
+            "the layout printed {:?} where the assembly prints {:?}",
+            String::from_utf8_lossy(&actual),
+            String::from_utf8_lossy(&expected)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(source: &str, input: &str, expected: &str) -> Assembly {
+        let program = parse(source).unwrap();
+        let assembly = assemble(&program);
+        verify(&program, &assembly, input.as_bytes(), 100_000)
+            .unwrap_or_else(|err| panic!("{}\n{}", err, assembly.code));
+        assert_eq!(
+            interpret(&program, input.as_bytes(), 100_000).unwrap(),
+            expected.as_bytes()
+        );
+        assembly
+    }
+
+    #[test]
+    fn extreme_numbers() {
+        check(
+            "push 9223372036854775807\nprint\npush -9223372036854775808\nprint",
+            "",
+            "9223372036854775807 -9223372036854775808 ",
+        );
+    }
+
+    #[test]
+    fn strings_and_characters() {
+        check(
+            "push 0\npush \"!dlrow olleh\"\nloop:\ndup\njz done\nprintc\njmp loop\ndone:",
+            "",
+            "hello world!",
+        );
+        check("push ';' ; a comment\nprintc", "", ";");
+    }
+
+    #[test]
+    fn loops_and_branches() {
+        // counts down from what it reads, then says whether it was even
+        let source = "
+            read
+            dup
+            push 2
+            mod
+            swap
+        loop:
+            dup
+            print
+            push 1
+            sub
+            dup
+            jnz loop
+            drop
+            jz even
+            push 'o'
+            printc
+            halt
+        even:
+            push 'e'
+            printc
+        ";
+        check(source, "3", "3 2 1 o");
+        check(source, "4", "4 3 2 1 e");
+    }
+
+    #[test]
+    fn calls_return_to_their_callers() {
+        let source = "
+            push 3
+            call twice
+            print
+            push 5
+            call twice
+            call twice
+            print
+            halt
+        twice:
+            push 2
+            mul
+            ret
+        ";
+        check(source, "", "6 20 ");
+    }
+
+    #[test]
+    fn source_map_points_at_cells() {
+        let assembly = check("push 1\nprint", "", "1 ");
+        assert_eq!(
+            assembly.map.lines,
+            vec![
+                Mapping {
+                    line: 1,
+                    cells: vec![(1, 1)],
+                },
+                Mapping {
+                    line: 2,
+                    cells: vec![(2, 1), (3, 1)],
+                },
+            ]
+        );
+        assert_eq!(assembly.code, "v\n>1.@\n");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse("push 1\njmp nowhere").unwrap_err();
+        assert_eq!(error.to_string(), "2: no label called nowhere");
+        assert!(parse("frob").is_err());
+        assert!(parse("ret").is_err());
+    }
+}
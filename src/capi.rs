@@ -0,0 +1,551 @@
+//! A C API for hosting the interpreter, behind the `capi` feature.
+//!
+//! `include/rsbefunge.h` is generated from this file with cbindgen, and the
+//! cdylib exports the functions it declares:
+//!
+//! ```c
+//! #include "rsbefunge.h"
+//!
+//! static ptrdiff_t print(void *user_data, const uint8_t *buf, size_t len) {
+//!     return fwrite(buf, 1, len, stdout);
+//! }
+//!
+//! FungeVm *vm = funge_vm_new((const uint8_t *)"\"!iH\",,,@", 9, NULL);
+//! funge_vm_set_output(vm, print, NULL);
+//! if (funge_vm_run(vm, 1000, NULL) != FUNGE_STATUS_STOPPED)
+//!     fprintf(stderr, "%s\n", funge_vm_last_error(vm));
+//! funge_vm_free(vm);
+//! ```
+//!
+//! A `FungeVm` belongs to one thread at a time and must be freed with
+//! `funge_vm_free`. Until a callback is set, output is dropped and input is
+//! at its end.
+
+// the comments on each function say what its pointers may be
+#![allow(clippy::missing_safety_doc)]
+
+use crate::funge::{self, Dialect, Direction, Limits, Location, Vm, VmBuilder, VmError};
+use std::cell::Cell;
+use std::ffi::{c_char, c_void, CString};
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+use std::{ptr, slice};
+
+/// Writes `len` bytes from `buf`, returning how many it wrote, at most `len`,
+/// or a negative number on error, which makes the instruction writing reflect
+pub type FungeWriteFn =
+    Option<extern "C" fn(user_data: *mut c_void, buf: *const u8, len: usize) -> isize>;
+
+/// Reads up to `len` bytes into `buf`, returning how many it read, 0 at end
+/// of input or a negative number on error
+pub type FungeReadFn =
+    Option<extern "C" fn(user_data: *mut c_void, buf: *mut u8, len: usize) -> isize>;
+
+/// How funge_vm_step and funge_vm_run went
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FungeStatus {
+    Running = 0,
+    /// With `@` or `q`, see funge_vm_exit_status
+    Stopped = 1,
+    /// The tick limit handed to funge_vm_run was reached
+    OutOfTicks = 2,
+    StackOverflow = 3,
+    SpaceFull = 4,
+    TooMuchOutput = 5,
+    TimedOut = 6,
+    Looping = 7,
+    /// A null pointer where one isn't allowed
+    InvalidArgument = 8,
+    Overflow = 9,
+}
+
+/// The way the IP is heading
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FungeDirection {
+    North = 0,
+    South = 1,
+    East = 2,
+    West = 3,
+}
+
+/// Settings for funge_vm_new, a null pointer means all of them are 0.
+/// Limits of 0 are unlimited, but for max_cells, where 0 is the default of
+/// 4194304 cells that aren't spaces.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FungeOptions {
+    pub befunge93: bool,
+    /// Seed `?` with `seed`, rather than at random
+    pub seeded: bool,
+    pub seed: u64,
+    pub max_stack: usize,
+    pub max_cells: usize,
+    pub max_output: usize,
+    pub max_time_ms: u64,
+}
+
+// a function pointer, null for none, and what to call it with
+#[derive(Clone, Copy)]
+struct Callback<F> {
+    function: F,
+    user_data: *mut c_void,
+}
+
+type Slot<F> = Rc<Cell<Callback<F>>>;
+
+fn slot<F: Default>() -> Slot<F> {
+    Rc::new(Cell::new(Callback {
+        function: F::default(),
+        user_data: ptr::null_mut(),
+    }))
+}
+
+/// A program and its callbacks, only ever handled through a pointer
+pub struct FungeVm {
+    vm: Vm,
+    input: Slot<FungeReadFn>,
+    output: Slot<FungeWriteFn>,
+    error: Option<CString>, // describes the last error status returned
+}
+
+impl FungeVm {
+    fn status(&mut self, result: Result<FungeStatus, VmError>) -> FungeStatus {
+        match result {
+            Ok(status) => {
+                self.error = None;
+                status
+            }
+            Err(err) => {
+                let status = match err {
+                    VmError::StackOverflow(_) => FungeStatus::StackOverflow,
+                    VmError::SpaceFull(_) => FungeStatus::SpaceFull,
+                    VmError::TooMuchOutput(_) => FungeStatus::TooMuchOutput,
+                    VmError::TimedOut(_) => FungeStatus::TimedOut,
+                    VmError::Looping(_) => FungeStatus::Looping,
+                    VmError::Overflow(_) => FungeStatus::Overflow,
+                };
+                self.error = CString::new(err.to_string()).ok();
+                status
+            }
+        }
+    }
+}
+
+/// Creates a VM running the `len` bytes at `source`, or returns null if
+/// `source` is null. `options` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_new(
+    source: *const u8,
+    len: usize,
+    options: *const FungeOptions,
+) -> *mut FungeVm {
+    if source.is_null() && len > 0 {
+        return ptr::null_mut();
+    }
+    let source = match len {
+        0 => &[][..],
+        _ => slice::from_raw_parts(source, len),
+    };
+    let options = options.as_ref().copied().unwrap_or_default();
+    let limit = |max: usize| Some(max).filter(|&max| max > 0);
+
+    let input = slot();
+    let output = slot();
+    let mut builder = VmBuilder::new()
+        .source(source)
+        .dialect(match options.befunge93 {
+            true => Dialect::Befunge93,
+            false => Dialect::Befunge98,
+        })
+        .limits(Limits {
+            max_stack: limit(options.max_stack),
+            max_cells: limit(options.max_cells).or(Some(Limits::DEFAULT_MAX_CELLS)),
+            max_output: limit(options.max_output),
+            max_time: Some(Duration::from_millis(options.max_time_ms))
+                .filter(|time| !time.is_zero()),
+        })
+        .input(Input::new(Rc::clone(&input)))
+        .output(Output(Rc::clone(&output)));
+    if options.seeded {
+        builder = builder.seed(options.seed);
+    }
+
+    Box::into_raw(Box::new(FungeVm {
+        vm: builder.build(),
+        input,
+        output,
+        error: None,
+    }))
+}
+
+/// Frees a VM made by funge_vm_new, doing nothing if `vm` is null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_free(vm: *mut FungeVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Sends output to `write`, or drops it if `write` is null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_set_output(
+    vm: *mut FungeVm,
+    write: FungeWriteFn,
+    user_data: *mut c_void,
+) {
+    if let Some(vm) = vm.as_mut() {
+        vm.output.set(Callback {
+            function: write,
+            user_data,
+        });
+    }
+}
+
+/// Reads input from `read`, or ends input if `read` is null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_set_input(
+    vm: *mut FungeVm,
+    read: FungeReadFn,
+    user_data: *mut c_void,
+) {
+    if let Some(vm) = vm.as_mut() {
+        vm.input.set(Callback {
+            function: read,
+            user_data,
+        });
+    }
+}
+
+/// Runs one instruction, returning FUNGE_STATUS_RUNNING, _STOPPED or an
+/// error
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_step(vm: *mut FungeVm) -> FungeStatus {
+    let Some(vm) = vm.as_mut() else {
+        return FungeStatus::InvalidArgument;
+    };
+    let result = vm.vm.tick().map(|stopped| match stopped {
+        true => FungeStatus::Stopped,
+        false => FungeStatus::Running,
+    });
+    vm.status(result)
+}
+
+/// Runs until the program stops or `max_ticks` have run (0 for no limit),
+/// storing how many ran in `ticks` unless it's null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_run(
+    vm: *mut FungeVm,
+    max_ticks: usize,
+    ticks: *mut usize,
+) -> FungeStatus {
+    let Some(vm) = vm.as_mut() else {
+        return FungeStatus::InvalidArgument;
+    };
+    let result = vm.vm.run(max_ticks).map(|outcome| {
+        if let Some(ticks) = ticks.as_mut() {
+            *ticks = outcome.ticks();
+        }
+        match outcome {
+            funge::Outcome::OutOfTicks { .. } => FungeStatus::OutOfTicks,
+            _ => FungeStatus::Stopped,
+        }
+    });
+    vm.status(result)
+}
+
+/// What went wrong, for the last error status funge_vm_step or funge_vm_run
+/// returned, or null. Valid until the next call with this VM.
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_last_error(vm: *const FungeVm) -> *const c_char {
+    match vm.as_ref().and_then(|vm| vm.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// How deep the stack is
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_stack_len(vm: *const FungeVm) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.vm.stack.as_slice().len())
+}
+
+/// Copies up to `len` cells from the top of the stack down into `cells`,
+/// returning how many it copied
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_stack(vm: *const FungeVm, cells: *mut i64, len: usize) -> usize {
+    let Some(vm) = vm.as_ref() else {
+        return 0;
+    };
+    if cells.is_null() {
+        return 0;
+    }
+    let cells = slice::from_raw_parts_mut(cells, len);
+    let stack = vm.vm.stack.as_slice().iter().rev();
+    let mut copied = 0;
+    for (cell, &value) in cells.iter_mut().zip(stack) {
+        *cell = funge::signed(value);
+        copied += 1;
+    }
+    copied
+}
+
+/// The cell at (x, y) in funge space
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_get(vm: *const FungeVm, x: i64, y: i64) -> i64 {
+    vm.as_ref().map_or(b' ' as i64, |vm| {
+        funge::signed(vm.vm.space.get(&Location(x, y)))
+    })
+}
+
+/// Where the IP is, storing whichever of `x` and `y` aren't null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_location(vm: *const FungeVm, x: *mut i64, y: *mut i64) {
+    if let Some(vm) = vm.as_ref() {
+        let Location(ipx, ipy) = vm.vm.get_location();
+        if let Some(x) = x.as_mut() {
+            *x = ipx;
+        }
+        if let Some(y) = y.as_mut() {
+            *y = ipy;
+        }
+    }
+}
+
+/// The way the IP is heading, east for a null `vm`
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_delta(vm: *const FungeVm) -> FungeDirection {
+    match vm.as_ref().map(|vm| vm.vm.get_delta()) {
+        Some(Direction::North) => FungeDirection::North,
+        Some(Direction::South) => FungeDirection::South,
+        Some(Direction::West) => FungeDirection::West,
+        _ => FungeDirection::East,
+    }
+}
+
+/// Whether the program quit with `q`, storing the status it popped in
+/// `status` unless that's null
+#[no_mangle]
+pub unsafe extern "C" fn funge_vm_exit_status(vm: *const FungeVm, status: *mut i32) -> bool {
+    match vm.as_ref().and_then(|vm| vm.vm.exit_status()) {
+        Some(code) => {
+            if let Some(status) = status.as_mut() {
+                *status = code;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+struct Output(Slot<FungeWriteFn>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Callback {
+            function: Some(write),
+            user_data,
+        } = self.0.get()
+        else {
+            return Ok(buf.len());
+        };
+        // claiming to have written more than it was given is as bad as failing
+        let written = write(user_data, buf.as_ptr(), buf.len());
+        usize::try_from(written)
+            .ok()
+            .filter(|written| *written <= buf.len())
+            .ok_or_else(|| io::Error::other("output callback failed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// buffers what the read callback hands back
+struct Input {
+    slot: Slot<FungeReadFn>,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl Input {
+    fn new(slot: Slot<FungeReadFn>) -> Input {
+        Input {
+            slot,
+            buffer: vec![0; 4096],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.start == self.end {
+            let Callback {
+                function: Some(read),
+                user_data,
+            } = self.slot.get()
+            else {
+                return Ok(&[]);
+            };
+            let read = read(user_data, self.buffer.as_mut_ptr(), self.buffer.len());
+            let read =
+                usize::try_from(read).map_err(|_| io::Error::other("input callback failed"))?;
+            (self.start, self.end) = (0, read.min(self.buffer.len()));
+        }
+        Ok(&self.buffer[self.start..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.start = (self.start + amt).min(self.end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    extern "C" fn collect(user_data: *mut c_void, buf: *const u8, len: usize) -> isize {
+        let output = unsafe { &mut *(user_data as *mut Vec<u8>) };
+        output.extend_from_slice(unsafe { slice::from_raw_parts(buf, len) });
+        len as isize
+    }
+
+    // claims to have written more than it was handed
+    extern "C" fn overcount(_: *mut c_void, _: *const u8, len: usize) -> isize {
+        len as isize + 1
+    }
+
+    // hands over the input two bytes at a time
+    extern "C" fn trickle(user_data: *mut c_void, buf: *mut u8, len: usize) -> isize {
+        let input = unsafe { &mut *(user_data as *mut &[u8]) };
+        let n = input.len().min(len).min(2);
+        unsafe { ptr::copy_nonoverlapping(input.as_ptr(), buf, n) };
+        *input = &input[n..];
+        n as isize
+    }
+
+    fn new(source: &str, options: Option<&FungeOptions>) -> *mut FungeVm {
+        let options = options.map_or(ptr::null(), |options| options as *const _);
+        unsafe { funge_vm_new(source.as_ptr(), source.len(), options) }
+    }
+
+    #[test]
+    fn runs_with_callbacks() {
+        let mut output: Vec<u8> = Vec::new();
+        let mut input: &[u8] = b"12 30";
+        let vm = new("&&+.@", None);
+        unsafe {
+            funge_vm_set_output(vm, Some(collect), &mut output as *mut _ as *mut c_void);
+            funge_vm_set_input(vm, Some(trickle), &mut input as *mut _ as *mut c_void);
+            let mut ticks = 0;
+            assert_eq!(funge_vm_run(vm, 100, &mut ticks), FungeStatus::Stopped);
+            assert_eq!(ticks, 5);
+            assert!(!funge_vm_exit_status(vm, ptr::null_mut()));
+            funge_vm_free(vm);
+        }
+        assert_eq!(output, b"42 ");
+    }
+
+    #[test]
+    fn output_callbacks_cant_overstate_what_they_wrote() {
+        // `.` reflects back through `1` and around to `@`
+        let vm = new("1.@", None);
+        unsafe {
+            funge_vm_set_output(vm, Some(overcount), ptr::null_mut());
+            let mut ticks = 0;
+            assert_eq!(funge_vm_run(vm, 100, &mut ticks), FungeStatus::Stopped);
+            assert_eq!(ticks, 4);
+            funge_vm_free(vm);
+        }
+    }
+
+    #[test]
+    fn steps_and_inspects_the_stack_and_ip() {
+        let vm = new("12-v\n   q", None);
+        unsafe {
+            for _ in 0..3 {
+                assert_eq!(funge_vm_step(vm), FungeStatus::Running);
+            }
+            let mut cells = [0; 4];
+            assert_eq!(funge_vm_stack_len(vm), 1);
+            assert_eq!(funge_vm_stack(vm, cells.as_mut_ptr(), cells.len()), 1);
+            assert_eq!(cells[0], -1);
+
+            assert_eq!(funge_vm_step(vm), FungeStatus::Running);
+            let (mut x, mut y) = (0, 0);
+            funge_vm_location(vm, &mut x, &mut y);
+            assert_eq!((x, y), (3, 1));
+            assert_eq!(funge_vm_delta(vm), FungeDirection::South);
+            assert_eq!(funge_vm_get(vm, 3, 1), b'q' as i64);
+
+            assert_eq!(funge_vm_step(vm), FungeStatus::Stopped);
+            let mut status = 0;
+            assert!(funge_vm_exit_status(vm, &mut status));
+            assert_eq!(status, -1);
+            funge_vm_free(vm);
+        }
+    }
+
+    #[test]
+    fn reports_broken_limits() {
+        let options = FungeOptions {
+            max_stack: 10,
+            ..FungeOptions::default()
+        };
+        let vm = new("1", Some(&options));
+        unsafe {
+            assert!(funge_vm_last_error(vm).is_null());
+            assert_eq!(
+                funge_vm_run(vm, 0, ptr::null_mut()),
+                FungeStatus::StackOverflow
+            );
+            let error = CStr::from_ptr(funge_vm_last_error(vm));
+            assert_eq!(error.to_str(), Ok("stack exceeded 10 cells"));
+            funge_vm_free(vm);
+
+            assert!(funge_vm_new(ptr::null(), 1, ptr::null()).is_null());
+            assert_eq!(funge_vm_step(ptr::null_mut()), FungeStatus::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn forgets_errors_once_a_call_succeeds() {
+        let vm = new("@", None);
+        unsafe {
+            let error = Err(VmError::StackOverflow(10));
+            assert_eq!((*vm).status(error), FungeStatus::StackOverflow);
+            assert!(!funge_vm_last_error(vm).is_null());
+            assert_eq!(funge_vm_step(vm), FungeStatus::Stopped);
+            assert!(funge_vm_last_error(vm).is_null());
+            funge_vm_free(vm);
+        }
+    }
+
+    #[test]
+    fn checked_in_header_is_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/rsbefunge.h");
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/rsbefunge.h"))
+                == include_str!("../include/rsbefunge.h"),
+            "include/rsbefunge.h is out of date, copy it from {}",
+            generated
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! Runs Funge programs against expected results kept next to them.
+//!
+//! A program `foo.b98` is a test case when at least one of these exists
+//! alongside it:
+//!
+//! - `foo.in`, fed to the program as its input
+//! - `foo.out`, the exact output the program should produce
+//! - `foo.exit`, the exit status it should finish with, 0 if missing
+//!
+//! Every case runs with captured I/O and a fixed seed so that results are
+//! reproducible. Programs ending in `.b93` run as Befunge-93, the rest as
+//! Funge-98.
+
+use crate::coverage::Coverage;
+use crate::funge::{Dialect, Limits, Outcome, SharedBuffer, VmBuilder};
+use similar::TextDiff;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+// extensions that mark a file as a Funge program
+const PROGRAM_EXTENSIONS: [&str; 4] = ["b98", "b93", "bf", "befunge"];
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub program: PathBuf,
+    pub input: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub exit: Option<PathBuf>,
+    pub dialect: Dialect, // from the program's extension
+}
+
+impl Case {
+    // the case for `program`, if it has any expectations next to it
+    pub fn for_program(program: &Path) -> Option<Case> {
+        let sibling = |ext: &str| Some(program.with_extension(ext)).filter(|p| p.is_file());
+        let case = Case {
+            program: program.to_path_buf(),
+            input: sibling("in"),
+            output: sibling("out"),
+            exit: sibling("exit"),
+            dialect: match program.extension().and_then(|ext| ext.to_str()) {
+                Some("b93") => Dialect::Befunge93,
+                _ => Dialect::Befunge98,
+            },
+        };
+
+        match (&case.input, &case.output, &case.exit) {
+            (None, None, None) => None,
+            _ => Some(case),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub seed: u64,
+    pub max_ticks: usize,
+    pub limits: Limits,
+    pub coverage: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            seed: 0,
+            max_ticks: 1_000_000,
+            limits: Limits::default(),
+            coverage: false,
+        }
+    }
+}
+
+// What happened when a case ran. A case passes when `failures` is empty.
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub case: Case,
+    pub output: Vec<u8>,
+    pub status: Option<i32>,
+    pub failures: Vec<String>,
+    pub coverage: Option<Coverage>, // when asked for in the options
+}
+
+impl Verdict {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// Finds every case under `paths`, which may be program files or
+// directories to search recursively. Cases come back sorted by path. A
+// program named on its own that isn't a case is an error, rather than
+// silently left out.
+pub fn discover(paths: &[PathBuf]) -> io::Result<Vec<Case>> {
+    let mut programs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            find_programs(path, &mut programs)?;
+        } else if Case::for_program(path).is_some() {
+            programs.push(path.clone());
+        } else {
+            let problem = match path.exists() {
+                true => "has no .in, .out or .exit file next to it",
+                false => "doesn't exist",
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} {}", path.display(), problem),
+            ));
+        }
+    }
+    programs.sort();
+    programs.dedup();
+
+    Ok(programs
+        .iter()
+        .filter_map(|p| Case::for_program(p))
+        .collect())
+}
+
+fn find_programs(dir: &Path, programs: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if name.starts_with('.') || name == "target" {
+            continue;
+        }
+
+        if path.is_dir() {
+            find_programs(&path, programs)?;
+        } else if is_program(&path) {
+            programs.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_program(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| PROGRAM_EXTENSIONS.contains(&ext))
+}
+
+pub fn run_case(case: &Case, options: &Options) -> io::Result<Verdict> {
+    let code = fs::read(&case.program)?;
+    let input = match &case.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let expected_output = match &case.output {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+    let expected_status = match &case.exit {
+        Some(path) => parse_status(path)?,
+        None => 0,
+    };
+
+    let output = SharedBuffer::new();
+    let mut vm = VmBuilder::new()
+        .source(code)
+        .dialect(case.dialect)
+        .input(Cursor::new(input))
+        .output(output.clone())
+        .seed(options.seed)
+        .limits(options.limits.clone())
+        .base_dir(
+            case.program
+                .parent()
+                .unwrap_or(Path::new("."))
+                .to_path_buf(),
+        )
+        .coverage(options.coverage)
+        .build();
+
+    let mut failures = Vec::new();
+    let status = match vm.run(options.max_ticks) {
+        Ok(Outcome::Stopped { .. }) => Some(0),
+        Ok(Outcome::Quit { status, .. }) => Some(status),
+        Ok(Outcome::OutOfTicks { ticks }) => {
+            failures.push(format!("still running after {} ticks", ticks));
+            None
+        }
+        Err(err) => {
+            failures.push(format!("error: {}", err));
+            None
+        }
+    };
+
+    if let Some(status) = status.filter(|s| *s != expected_status) {
+        failures.push(format!(
+            "exit status {}, expected {}",
+            status, expected_status
+        ));
+    }
+
+    let output = output.take();
+    if let Some(expected) = expected_output.filter(|e| *e != output) {
+        failures.push(format!("output differs\n{}", diff(&expected, &output)));
+    }
+
+    Ok(Verdict {
+        case: case.clone(),
+        output,
+        status,
+        failures,
+        coverage: vm.coverage().cloned(),
+    })
+}
+
+fn parse_status(path: &Path) -> io::Result<i32> {
+    let text = fs::read_to_string(path)?;
+    text.trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "exit status is not a number"))
+}
+
+// a unified diff of the expected output against the actual output
+pub fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let (expected, actual) = (
+        String::from_utf8_lossy(expected),
+        String::from_utf8_lossy(actual),
+    );
+    TextDiff::from_lines(expected.as_ref(), actual.as_ref())
+        .unified_diff()
+        .header("expected", "actual")
+        .missing_newline_hint(true)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory holding `files`, removed again by the caller
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rsbefunge-conformance-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn cases_need_expectations_next_to_them() {
+        let dir = directory(
+            "case",
+            &[("a.b98", "@"), ("a.in", ""), ("b.b93", "@"), ("c.b98", "@")],
+        );
+
+        let a = Case::for_program(&dir.join("a.b98")).unwrap();
+        assert_eq!(a.input, Some(dir.join("a.in")));
+        assert_eq!((a.output, a.exit), (None, None));
+        assert_eq!(a.dialect, Dialect::Befunge98);
+        assert!(Case::for_program(&dir.join("b.b93")).is_none());
+        assert!(Case::for_program(&dir.join("c.b98")).is_none());
+
+        fs::write(dir.join("b.exit"), "0").unwrap();
+        let b = Case::for_program(&dir.join("b.b93")).unwrap();
+        assert_eq!(b.dialect, Dialect::Befunge93);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn discovers_cases_in_order() {
+        let dir = directory(
+            "discover",
+            &[
+                ("z.b98", "@"),
+                ("z.out", ""),
+                ("sub/a.bf", "@"),
+                ("sub/a.exit", "0"),
+                ("skipped.b98", "@"),
+                ("notes.out", ""),
+                (".hidden/h.b98", "@"),
+                (".hidden/h.out", ""),
+            ],
+        );
+
+        let cases = discover(&[dir.clone(), dir.join("z.b98")]).unwrap();
+        let programs: Vec<_> = cases.iter().map(|c| c.program.clone()).collect();
+        assert_eq!(programs, [dir.join("sub/a.bf"), dir.join("z.b98")]);
+
+        assert!(discover(&[dir.join("skipped.b98")]).is_err());
+        assert!(discover(&[dir.join("missing.b98")]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn runs_each_case_in_its_dialect() {
+        let dir = directory(
+            "dialect",
+            &[
+                ("old.b93", "a.@"),
+                ("old.out", "97 "),
+                ("new.b98", "a.@"),
+                ("new.out", "97 "),
+            ],
+        );
+
+        let old = Case::for_program(&dir.join("old.b93")).unwrap();
+        assert!(run_case(&old, &Options::default()).unwrap().passed());
+        let new = Case::for_program(&dir.join("new.b98")).unwrap();
+        let verdict = run_case(&new, &Options::default()).unwrap();
+        assert_eq!(verdict.output, b"10 ");
+        assert!(verdict.failures[0].starts_with("output differs"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_exit_statuses() {
+        let dir = directory("status", &[("good.exit", " 3\n"), ("bad.exit", "three")]);
+        assert_eq!(parse_status(&dir.join("good.exit")).unwrap(), 3);
+        let err = parse_status(&dir.join("bad.exit")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diffs_expected_against_actual_output() {
+        assert_eq!(diff(b"same\n", b"same\n"), "");
+        let diff = diff(b"a\nb\n", b"a\nc\n");
+        assert!(diff.contains("--- expected"), "{}", diff);
+        assert!(diff.contains("-b\n+c\n"), "{}", diff);
+    }
+}
@@ -0,0 +1,306 @@
+//! Records which cells a program ran and which way its branches went.
+//!
+//! A [`Coverage`] is collected by a [`crate::funge::Vm`] built with
+//! `coverage(true)`. A [`Report`] keeps one per program and is merged with
+//! whatever is already in the report file, so that several runs, or a
+//! whole test suite, add up to a single report. Next to the JSON report, at
+//! its path with `.txt` added, an annotated copy of each program is written
+//! with a marker row under every row of source:
+//!
+//! - `#` ran
+//! - `!` never ran
+//! - `+` a `_` or `|` that went both ways
+//! - `<` `>` `^` `v` a `_` or `|` that only ever went that way
+
+use crate::funge::{Cell, Direction, Location, Space};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// How often a `_` or `|` sent the IP each way
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Branch {
+    pub x: usize,
+    pub y: usize,
+    pub north: u64,
+    pub east: u64,
+    pub south: u64,
+    pub west: u64,
+}
+
+impl Branch {
+    fn directions(&self) -> usize {
+        [self.north, self.east, self.south, self.west]
+            .iter()
+            .filter(|n| **n > 0)
+            .count()
+    }
+}
+
+// Coverage of a single program
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coverage {
+    pub source: Vec<String>,
+    pub hits: Vec<Vec<u64>>, // how often each cell ran, by row then column
+    pub branches: Vec<Branch>,
+}
+
+impl Coverage {
+    // coverage of the program as it is laid out in `space`, with nothing run
+    pub fn new<T: Cell>(space: &Space<T>) -> Coverage {
+        let (w, h) = space.dims();
+        let source = (0..h)
+            .map(|y| {
+                let row: String = (0..w)
+                    .map(|x| space.get(&Location(x as i64, y as i64)).as_i64() as u8 as char)
+                    .collect();
+                row.trim_end().to_string()
+            })
+            .collect();
+
+        Coverage {
+            source,
+            hits: vec![vec![0; w]; h],
+            branches: Vec::new(),
+        }
+    }
+
+    pub fn hit(&mut self, x: usize, y: usize) {
+        // cells the program grew into with `p` aren't part of its source, and
+        // may be anywhere in funge space
+        if let Some(hits) = self.hits.get_mut(y).and_then(|row| row.get_mut(x)) {
+            *hits += 1;
+        }
+    }
+
+    pub fn branch(&mut self, x: usize, y: usize, went: &Direction) {
+        let index = match self.branches.iter().position(|b| b.x == x && b.y == y) {
+            Some(index) => index,
+            None => {
+                self.branches.push(Branch {
+                    x,
+                    y,
+                    ..Branch::default()
+                });
+                self.branches.len() - 1
+            }
+        };
+
+        let branch = &mut self.branches[index];
+        match went {
+            Direction::North => branch.north += 1,
+            Direction::East => branch.east += 1,
+            Direction::South => branch.south += 1,
+            Direction::West => branch.west += 1,
+        }
+    }
+
+    fn hits_at(&self, x: usize, y: usize) -> u64 {
+        self.hits
+            .get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // adds the counts from another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (y, row) in other.hits.iter().enumerate() {
+            for (x, hits) in row.iter().enumerate().filter(|(_, h)| **h > 0) {
+                if self.hits.len() <= y {
+                    self.hits.resize(y + 1, Vec::new());
+                }
+                if self.hits[y].len() <= x {
+                    self.hits[y].resize(x + 1, 0);
+                }
+                self.hits[y][x] += hits;
+            }
+        }
+
+        for branch in other.branches.iter() {
+            match self
+                .branches
+                .iter_mut()
+                .find(|b| b.x == branch.x && b.y == branch.y)
+            {
+                Some(b) => {
+                    b.north += branch.north;
+                    b.east += branch.east;
+                    b.south += branch.south;
+                    b.west += branch.west;
+                }
+                None => self.branches.push(branch.clone()),
+            }
+        }
+        self.branches.sort_by_key(|b| (b.y, b.x));
+    }
+
+    // the source with a row of markers under each row
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let (mut ran, mut cells) = (0, 0);
+        for (y, line) in self.source.iter().enumerate() {
+            let mut markers = String::new();
+            for (x, c) in line.chars().enumerate() {
+                let hits = self.hits_at(x, y);
+                if c != ' ' {
+                    cells += 1;
+                    if hits > 0 {
+                        ran += 1;
+                    }
+                }
+
+                let branch = self.branches.iter().find(|b| b.x == x && b.y == y);
+                markers.push(match (c, hits, branch) {
+                    (' ', _, _) => ' ',
+                    (_, 0, _) => '!',
+                    (_, _, Some(b)) if b.directions() > 1 => '+',
+                    (_, _, Some(b)) if b.north > 0 => '^',
+                    (_, _, Some(b)) if b.east > 0 => '>',
+                    (_, _, Some(b)) if b.south > 0 => 'v',
+                    (_, _, Some(_)) => '<',
+                    _ => '#',
+                });
+            }
+            writeln!(out, "{}", line).ok();
+            writeln!(out, "{}", markers.trim_end()).ok();
+        }
+
+        let taken: usize = self.branches.iter().map(|b| b.directions()).sum();
+        writeln!(
+            out,
+            "{} of {} cells ran, {} of {} branch directions taken",
+            ran,
+            cells,
+            taken,
+            self.branches.len() * 2
+        )
+        .ok();
+        out
+    }
+}
+
+// Coverage for every program run, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report {
+    pub programs: BTreeMap<String, Coverage>,
+}
+
+impl Report {
+    // the report in `path`, or an empty one if there isn't one yet
+    pub fn load(path: &Path) -> io::Result<Report> {
+        match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Report::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Adds a run of the program `name`. If the program has changed since it
+    // was last recorded its old coverage is thrown away.
+    pub fn add(&mut self, name: &str, coverage: &Coverage) {
+        match self.programs.get_mut(name) {
+            Some(existing) if existing.source == coverage.source => existing.merge(coverage),
+            _ => {
+                self.programs.insert(name.to_string(), coverage.clone());
+            }
+        }
+    }
+
+    // writes the JSON report to `path` and the annotated programs next to it
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, json + "\n")?;
+        fs::write(Report::rendering_path(path), self.render())
+    }
+
+    // `path` with `.txt` added, so it can't be the report itself
+    pub fn rendering_path(path: &Path) -> PathBuf {
+        let mut rendering = path.as_os_str().to_owned();
+        rendering.push(".txt");
+        PathBuf::from(rendering)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, coverage) in self.programs.iter() {
+            writeln!(out, "{}", name).ok();
+            out.push_str(&coverage.render());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &str) -> Coverage {
+        let mut coverage = Coverage::new(&Space::<usize>::new(code.as_bytes()));
+        for x in 0..code.find('@').unwrap() {
+            coverage.hit(x, 0);
+        }
+        coverage
+    }
+
+    #[test]
+    fn merging_adds_up_hits_and_branches() {
+        let mut a = run("1_@");
+        a.branch(1, 0, &Direction::East);
+        let mut b = run("1_@");
+        b.branch(1, 0, &Direction::West);
+        b.hit(4, 2); // off the end of the source
+
+        a.merge(&b);
+        assert_eq!(a.hits, vec![vec![2, 2, 0]]);
+        assert_eq!(a.branches[0].east, 1);
+        assert_eq!(a.branches[0].west, 1);
+    }
+
+    #[test]
+    fn render_marks_cells_and_branches() {
+        let mut coverage = run("1_@ .");
+        coverage.branch(1, 0, &Direction::East);
+        assert_eq!(
+            coverage.render(),
+            "1_@ .\n#>! !\n2 of 4 cells ran, 1 of 2 branch directions taken\n"
+        );
+    }
+
+    #[test]
+    fn report_replaces_changed_programs() {
+        let mut report = Report::default();
+        report.add("p", &run("12@"));
+        report.add("p", &run("12@"));
+        assert_eq!(report.programs["p"].hits[0], vec![2, 2, 0]);
+
+        report.add("p", &run("123@"));
+        assert_eq!(report.programs["p"].hits[0], vec![1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn rendering_sits_next_to_the_report() {
+        let dir = std::env::temp_dir().join(format!("rsbefunge-coverage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.txt");
+
+        let mut report = Report::default();
+        report.add("p", &run("12@"));
+        report.save(&path).unwrap();
+        report.save(&path).unwrap();
+        assert_eq!(
+            Report::load(&path).unwrap().programs["p"].hits[0],
+            vec![1, 1, 0]
+        );
+        assert!(fs::read_to_string(dir.join("report.txt.txt"))
+            .unwrap()
+            .starts_with("p\n12@"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,583 @@
+//! A Debug Adapter Protocol server for Befunge programs.
+//!
+//! [`serve`] speaks DAP over a pair of streams, normally stdin and stdout,
+//! and drives a [`Vm`] one tick at a time. It supports:
+//!
+//! - breakpoints on a cell, or with no column on a row, stopping whenever
+//!   the IP moves onto that row
+//! - continuing, and stepping a single tick with next, step in or step out
+//! - variables for the stack, top first, the IP's position, delta and
+//!   string mode, and the rows of funge space
+//! - reading funge space as memory, one byte per cell row by row
+//!
+//! `launch` takes either a `program` path or the `source` itself, plus an
+//! optional `input` string for the program, `stopOnEntry` and `maxTicks`.
+//! The adapter can't be interrupted while it runs, so `pause` is refused
+//! and `continue` gives up and reports a pause after `maxTicks` ticks, a
+//! million by default.
+
+use crate::funge::{Direction, Location, SharedBuffer, Vm, VmBuilder};
+use crate::lsp::{read_message, write_message};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Write};
+use std::path::PathBuf;
+
+const THREAD_ID: u64 = 1;
+
+// variable references for the scopes
+const STACK: u64 = 1;
+const IP: u64 = 2;
+const SPACE: u64 = 3;
+
+// the memory reference for funge space
+const MEMORY: &str = "space";
+
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut adapter = Adapter {
+        output,
+        seq: 0,
+        session: None,
+    };
+
+    while let Some(message) = read_message(&mut input)? {
+        if !adapter.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// a program being debugged
+struct Session {
+    vm: Vm,
+    program: Option<PathBuf>,
+    output: SharedBuffer,
+    breakpoints: HashSet<(Option<usize>, usize)>, // (column, row)
+    stop_on_entry: bool,
+    max_ticks: usize,
+    paused: bool, // whether the client has been told it stopped
+    finished: bool,
+}
+
+struct Adapter<W: Write> {
+    output: W,
+    seq: u64,
+    session: Option<Session>,
+}
+
+// how a run of ticks ended
+enum Pause {
+    Breakpoint,
+    Step,
+    Limit,
+    Exited(i32),
+}
+
+impl<W: Write> Adapter<W> {
+    // handles a request, returning false once the client disconnects
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+
+        let body = match command {
+            "initialize" => {
+                self.respond(
+                    request,
+                    Ok(json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsReadMemoryRequest": true,
+                        "supportsTerminateRequest": true,
+                    })),
+                )?;
+                self.event("initialized", json!({}))?;
+                return Ok(true);
+            }
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "configurationDone" => {
+                self.respond(request, Ok(json!({})))?;
+                let stop_on_entry = self.session.as_ref().is_some_and(|s| s.stop_on_entry);
+                return match stop_on_entry {
+                    true => self.stopped("entry").map(|_| true),
+                    false => self.resume(false).map(|_| true),
+                };
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                    { "name": "IP", "variablesReference": IP, "expensive": false },
+                    { "name": "Space", "variablesReference": SPACE, "expensive": false },
+                ]
+            })),
+            "variables" => self.variables(args["variablesReference"].as_u64().unwrap_or(0)),
+            "readMemory" => self.read_memory(args),
+            "continue" => {
+                self.respond(request, Ok(json!({ "allThreadsContinued": true })))?;
+                return self.resume(false).map(|_| true);
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(json!({})))?;
+                return self.resume(true).map(|_| true);
+            }
+            "pause" => Err("a running program can't be paused, set maxTicks instead".to_string()),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})))?;
+                if command == "terminate" {
+                    self.event("terminated", json!({}))?;
+                }
+                return Ok(command != "disconnect");
+            }
+            _ => Err(format!("unsupported request {}", command)),
+        };
+
+        self.respond(request, body)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().map(PathBuf::from);
+        let source = match (&program, args["source"].as_str()) {
+            (_, Some(source)) => source.as_bytes().to_vec(),
+            (Some(path), None) => fs::read(path)
+                .map_err(|err| format!("couldn't read {}: {}", path.display(), err))?,
+            (None, None) => return Err("launch needs a program or its source".to_string()),
+        };
+        let input = args["input"].as_str().unwrap_or("").as_bytes().to_vec();
+
+        let output = SharedBuffer::new();
+        let mut builder = VmBuilder::new()
+            .source(source)
+            .input(Cursor::new(input))
+            .output(output.clone());
+        if let Some(dir) = program.as_ref().and_then(|p| p.parent()) {
+            builder = builder.base_dir(dir.to_path_buf());
+        }
+
+        self.session = Some(Session {
+            vm: builder.build(),
+            program,
+            output,
+            breakpoints: HashSet::new(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            max_ticks: args["maxTicks"].as_u64().unwrap_or(1_000_000) as usize,
+            paused: false,
+            finished: false,
+        });
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+
+        // lines and columns count from 1 in DAP
+        session.breakpoints.clear();
+        let mut verified = Vec::new();
+        for breakpoint in requested.iter() {
+            let line = breakpoint["line"].as_u64().unwrap_or(1).max(1) as usize;
+            let column = breakpoint["column"].as_u64().map(|c| c.max(1) as usize);
+            session
+                .breakpoints
+                .insert((column.map(|c| c - 1), line - 1));
+            verified.push(json!({ "verified": true, "line": line, "column": column }));
+        }
+        Ok(json!({ "breakpoints": verified }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        let at = session.vm.get_location();
+        let cell = session.vm.space.get(&at) as u8 as char;
+
+        let mut frame = json!({
+            "id": 1,
+            "name": format!("{:?} at ({}, {})", cell, at.0, at.1),
+            "line": at.1 + 1,
+            "column": at.0 + 1,
+        });
+        if let Some(path) = &session.program {
+            frame["source"] = json!({ "path": path.display().to_string() });
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&self, reference: u64) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        let vm = &session.vm;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let variables: Vec<Value> = match reference {
+            STACK => vm
+                .stack
+                .as_slice()
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(i, value)| variable(i.to_string(), describe_cell(*value)))
+                .collect(),
+            IP => {
+                let at = vm.get_location();
+                vec![
+                    variable("position".to_string(), format!("({}, {})", at.0, at.1)),
+                    variable("delta".to_string(), describe_delta(&vm.get_delta())),
+                    variable("string mode".to_string(), vm.get_string_mode().to_string()),
+                ]
+            }
+            // the program's rectangle, however far away cells have been put
+            SPACE => {
+                let (w, h) = vm.space.dims();
+                (0..h)
+                    .map(|y| {
+                        let row: String = (0..w)
+                            .map(|x| vm.space.get(&Location(x as i64, y as i64)) as u8 as char)
+                            .collect();
+                        let mut v = variable(y.to_string(), row.trim_end().to_string());
+                        v["memoryReference"] = json!(MEMORY);
+                        v
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    // funge space as bytes, row after row, each cell truncated to a byte
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        if args["memoryReference"].as_str() != Some(MEMORY) {
+            return Err("unknown memory reference".to_string());
+        }
+
+        let (w, h) = session.vm.space.dims();
+        let offset = args["offset"].as_i64().unwrap_or(0).max(0) as usize;
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let size = w.checked_mul(h).ok_or("funge space is too large to read")?;
+        let end = offset.saturating_add(count).min(size);
+        let bytes: Vec<u8> = (offset.min(end)..end)
+            .map(|i| {
+                let at = Location((i % w) as i64, (i / w) as i64);
+                session.vm.space.get(&at) as u8
+            })
+            .collect();
+
+        Ok(json!({
+            "address": offset.to_string(),
+            "unreadableBytes": count - bytes.len(),
+            "data": base64(&bytes),
+        }))
+    }
+
+    // runs the program until it pauses, reporting how and why
+    fn resume(&mut self, single_step: bool) -> io::Result<()> {
+        let pause = match self.session.as_mut() {
+            Some(session) if !session.finished => run(session, single_step),
+            _ => return Ok(()),
+        };
+        self.flush_output()?;
+
+        match pause {
+            Pause::Breakpoint => self.stopped("breakpoint"),
+            Pause::Step => self.stopped("step"),
+            Pause::Limit => self.stopped("pause"),
+            Pause::Exited(status) => {
+                if let Some(session) = self.session.as_mut() {
+                    session.finished = true;
+                }
+                self.event("exited", json!({ "exitCode": status }))?;
+                self.event("terminated", json!({}))
+            }
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let written = match &self.session {
+            Some(session) => session.output.take(),
+            None => return Ok(()),
+        };
+        if written.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&written).into_owned();
+        self.event("output", json!({ "category": "stdout", "output": text }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        if let Some(session) = self.session.as_mut() {
+            session.paused = true;
+        }
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) -> io::Result<()> {
+        self.seq += 1;
+        let mut response = json!({
+            "seq": self.seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        write_message(&mut self.output, &response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.seq += 1;
+        write_message(
+            &mut self.output,
+            &json!({ "seq": self.seq, "type": "event", "event": event, "body": body }),
+        )
+    }
+}
+
+// ticks until a breakpoint, the end of the program or the tick limit, or
+// just once when stepping
+fn run(session: &mut Session, single_step: bool) -> Pause {
+    let max_ticks = session.max_ticks.max(1);
+    let mut row = None; // the row the IP was on a tick ago
+    for ticks in 0..=max_ticks {
+        // breakpoints stop the IP before it runs the cell they're on, other
+        // than the one it was already stopped on
+        let at = session.vm.get_location();
+        let (x, y) = (at.0 as usize, at.1 as usize);
+        let resuming = ticks == 0 && session.paused;
+        if !resuming
+            && (session.breakpoints.contains(&(Some(x), y))
+                || (row != Some(at.1) && session.breakpoints.contains(&(None, y))))
+        {
+            return Pause::Breakpoint;
+        }
+        if ticks == max_ticks {
+            break;
+        }
+
+        row = Some(at.1);
+        match session.vm.tick() {
+            Ok(true) => return Pause::Exited(session.vm.exit_status().unwrap_or(0)),
+            Err(err) => {
+                let message = format!("Error: {}\n", err);
+                session.output.clone().write_all(message.as_bytes()).ok();
+                return Pause::Exited(1);
+            }
+            Ok(false) => (),
+        }
+
+        if single_step {
+            return Pause::Step;
+        }
+    }
+    Pause::Limit
+}
+
+fn describe_cell(value: usize) -> String {
+    let signed = value as i64;
+    match u8::try_from(value) {
+        Ok(byte) if byte.is_ascii_graphic() || byte == b' ' => {
+            format!("{} {:?}", signed, byte as char)
+        }
+        _ => signed.to_string(),
+    }
+}
+
+fn describe_delta(delta: &Direction) -> String {
+    format!("{:?}", delta).to_lowercase()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs a session and returns everything the adapter sent back
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let request = json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut replies = Vec::new();
+        let mut output = Cursor::new(output);
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn find<'a>(replies: &'a [Value], kind: &str, name: &str) -> Vec<&'a Value> {
+        replies
+            .iter()
+            .filter(|r| r["type"] == kind && (r["command"] == name || r["event"] == name))
+            .collect()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_shows_the_stack() {
+        let replies = session(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "source": "12+v\n   >.@" })),
+            (
+                "setBreakpoints",
+                json!({ "breakpoints": [{ "line": 1, "column": 4 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("variables", json!({ "variablesReference": STACK })),
+            ("variables", json!({ "variablesReference": IP })),
+            ("continue", json!({})),
+            ("disconnect", json!({})),
+        ]);
+
+        assert_eq!(
+            find(&replies, "event", "stopped")[0]["body"]["reason"],
+            "breakpoint"
+        );
+        let variables = find(&replies, "response", "variables");
+        assert_eq!(variables[0]["body"]["variables"][0]["value"], "3");
+        assert_eq!(variables[1]["body"]["variables"][0]["value"], "(3, 0)");
+        assert_eq!(variables[1]["body"]["variables"][1]["value"], "east");
+        assert_eq!(find(&replies, "event", "output")[0]["body"]["output"], "3 ");
+        assert_eq!(find(&replies, "event", "exited")[0]["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn steps_one_tick_at_a_time() {
+        let replies = session(&[
+            ("initialize", json!({})),
+            (
+                "launch",
+                json!({ "source": "1\"a\"@", "stopOnEntry": true }),
+            ),
+            ("configurationDone", json!({})),
+            ("next", json!({})),
+            ("next", json!({})),
+            ("variables", json!({ "variablesReference": IP })),
+            ("disconnect", json!({})),
+        ]);
+
+        let stops = find(&replies, "event", "stopped");
+        assert_eq!(stops[0]["body"]["reason"], "entry");
+        assert_eq!(stops[2]["body"]["reason"], "step");
+        let ip = find(&replies, "response", "variables")[0];
+        assert_eq!(ip["body"]["variables"][0]["value"], "(2, 0)");
+        assert_eq!(ip["body"]["variables"][2]["value"], "true");
+    }
+
+    #[test]
+    fn reads_space_as_memory() {
+        let replies = session(&[
+            ("launch", json!({ "source": "ab@\ncd" })),
+            (
+                "readMemory",
+                json!({ "memoryReference": MEMORY, "offset": 3, "count": 4 }),
+            ),
+        ]);
+        let memory = find(&replies, "response", "readMemory")[0];
+        assert_eq!(memory["body"]["data"], base64(b"cd "));
+        assert_eq!(memory["body"]["unreadableBytes"], 1);
+    }
+
+    #[test]
+    fn reads_past_the_end_of_memory() {
+        let replies = session(&[
+            ("launch", json!({ "source": "ab@" })),
+            (
+                "readMemory",
+                json!({ "memoryReference": MEMORY, "offset": 1, "count": u64::MAX }),
+            ),
+        ]);
+        let memory = find(&replies, "response", "readMemory")[0];
+        assert_eq!(memory["body"]["data"], base64(b"b@"));
+    }
+
+    #[test]
+    fn shows_only_the_program_after_a_far_put() {
+        let replies = session(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "source": "\"X\"99*:*:*:*0p@" })),
+            ("configurationDone", json!({})),
+            ("variables", json!({ "variablesReference": SPACE })),
+            (
+                "readMemory",
+                json!({ "memoryReference": MEMORY, "count": u64::MAX }),
+            ),
+        ]);
+        let space = find(&replies, "response", "variables")[0];
+        assert_eq!(space["body"]["variables"].as_array().unwrap().len(), 1);
+        let memory = find(&replies, "response", "readMemory")[0];
+        assert_eq!(memory["body"]["data"], base64(b"\"X\"99*:*:*:*0p@"));
+    }
+
+    #[test]
+    fn stops_at_a_breakpoint_on_the_first_cell() {
+        let replies = session(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "source": "1.@" })),
+            (
+                "setBreakpoints",
+                json!({ "breakpoints": [{ "line": 1, "column": 1 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("variables", json!({ "variablesReference": IP })),
+            ("continue", json!({})),
+            ("disconnect", json!({})),
+        ]);
+
+        let stops = find(&replies, "event", "stopped");
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0]["body"]["reason"], "breakpoint");
+        let ip = find(&replies, "response", "variables")[0];
+        assert_eq!(ip["body"]["variables"][0]["value"], "(0, 0)");
+        assert_eq!(find(&replies, "event", "output")[0]["body"]["output"], "1 ");
+    }
+
+    #[test]
+    fn refuses_to_pause() {
+        let replies = session(&[
+            ("launch", json!({ "source": "@" })),
+            ("pause", json!({ "threadId": THREAD_ID })),
+        ]);
+        assert_eq!(find(&replies, "response", "pause")[0]["success"], false);
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+}
@@ -0,0 +1,389 @@
+//! Finds short ways to push numbers and text.
+//!
+//! [`number`] builds a value from the digits, `0`-`9` in Befunge-93 and
+//! `0`-`f` in Funge-98, and `+ - * :`. Up to [`EXACT`] either way it's the
+//! shortest expression that joins two smaller ones with `+`, `-` or `*`, or
+//! doubles or squares one with `:+` or `:*`, and whose intermediate values
+//! stay within that range. Other ways of using `:` aren't searched, so a
+//! shorter program can exist. Beyond it, it's the shortest way of writing
+//! the value out in any single base up to [`EXACT`].
+//!
+//! [`string`] pushes text in string mode where it can, leaving it for
+//! characters a string can't hold and for repeats that are cheaper to
+//! duplicate. For Funge-98 it never quotes two spaces in a row, which the
+//! spec lets an interpreter push as one.
+//!
+//! Both check what they produce with [`evaluate`], which runs it on a
+//! [`Stack`] with the VM's own operators.
+
+use crate::funge::ops::NAry;
+use crate::funge::{Dialect, Lifo, Stack};
+use std::sync::OnceLock;
+
+// numbers up to this, positive or negative, are looked up in a table
+pub const EXACT: usize = 4096;
+
+// how the cheapest expression for a value is put together
+#[derive(Debug, Clone, Copy)]
+enum Recipe {
+    Digit,
+    Add(u16, u16),
+    Sub(u16, u16),
+    Mul(u16, u16),
+    Double(u16), // x:+
+    Square(u16), // x:*
+}
+
+struct Table {
+    cost: Vec<usize>,
+    recipe: Vec<Recipe>,
+}
+
+fn table(dialect: Dialect) -> &'static Table {
+    static BEFUNGE93: OnceLock<Table> = OnceLock::new();
+    static BEFUNGE98: OnceLock<Table> = OnceLock::new();
+    match dialect {
+        Dialect::Befunge93 => BEFUNGE93.get_or_init(|| Table::build(10)),
+        Dialect::Befunge98 => BEFUNGE98.get_or_init(|| Table::build(16)),
+    }
+}
+
+impl Table {
+    fn build(digits: usize) -> Table {
+        let mut cost = vec![usize::MAX / 4; EXACT + 1];
+        let mut recipe = vec![Recipe::Digit; EXACT + 1];
+        for c in cost.iter_mut().take(digits) {
+            *c = 1;
+        }
+
+        // subtraction reaches down from larger values, so go round until
+        // nothing gets cheaper
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for v in digits..=EXACT {
+                let mut best = (cost[v], recipe[v]);
+
+                if v % 2 == 0 {
+                    cheaper(&mut best, cost[v / 2] + 2, Recipe::Double((v / 2) as u16));
+                }
+                let root = v.isqrt();
+                if root * root == v {
+                    cheaper(&mut best, cost[root] + 2, Recipe::Square(root as u16));
+                }
+                for a in 2..=root {
+                    if v % a == 0 {
+                        let b = v / a;
+                        cheaper(
+                            &mut best,
+                            cost[a] + cost[b] + 1,
+                            Recipe::Mul(a as u16, b as u16),
+                        );
+                    }
+                }
+                for a in 1..=v / 2 {
+                    if cost[a] + 2 < best.0 {
+                        let b = v - a;
+                        cheaper(
+                            &mut best,
+                            cost[a] + cost[b] + 1,
+                            Recipe::Add(a as u16, b as u16),
+                        );
+                    }
+                }
+                for b in 1..=EXACT - v {
+                    if cost[b] + 2 < best.0 {
+                        let a = v + b;
+                        cheaper(
+                            &mut best,
+                            cost[a] + cost[b] + 1,
+                            Recipe::Sub(a as u16, b as u16),
+                        );
+                    }
+                }
+
+                if best.0 < cost[v] {
+                    (cost[v], recipe[v]) = best;
+                    changed = true;
+                }
+            }
+        }
+        Table { cost, recipe }
+    }
+
+    fn write(&self, v: usize, out: &mut Vec<u8>) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        match self.recipe[v] {
+            Recipe::Digit => out.push(DIGITS[v]),
+            Recipe::Add(a, b) => self.binary(a, b, b'+', out),
+            Recipe::Sub(a, b) => self.binary(a, b, b'-', out),
+            Recipe::Mul(a, b) => self.binary(a, b, b'*', out),
+            Recipe::Double(x) => {
+                self.write(x as usize, out);
+                out.extend(b":+");
+            }
+            Recipe::Square(x) => {
+                self.write(x as usize, out);
+                out.extend(b":*");
+            }
+        }
+    }
+
+    fn binary(&self, a: u16, b: u16, op: u8, out: &mut Vec<u8>) {
+        self.write(a as usize, out);
+        self.write(b as usize, out);
+        out.push(op);
+    }
+}
+
+fn cheaper(best: &mut (usize, Recipe), cost: usize, recipe: Recipe) {
+    if cost < best.0 {
+        *best = (cost, recipe);
+    }
+}
+
+// The cells that push `n`
+pub fn number(n: i64, dialect: Dialect) -> String {
+    let table = table(dialect);
+    let magnitude = n.unsigned_abs();
+    let mut out = Vec::new();
+
+    match (n < 0, magnitude as usize <= EXACT) {
+        (false, true) => table.write(n as usize, &mut out),
+        // a - b for some a smaller than b, which includes 0 - |n|
+        (true, true) => {
+            let m = magnitude as usize;
+            let a = (0..=EXACT - m)
+                .min_by_key(|a| table.cost[*a] + table.cost[a + m])
+                .unwrap_or(0);
+            table.write(a, &mut out);
+            table.write(a + m, &mut out);
+            out.push(b'-');
+        }
+        (negative, false) => {
+            if negative {
+                out.push(b'0');
+            }
+            let base = (2..=EXACT as u64)
+                .min_by_key(|base| horner_cost(table, magnitude, *base))
+                .unwrap();
+            horner(table, magnitude, base, &mut out);
+            if negative {
+                out.push(b'-');
+            }
+        }
+    }
+    String::from_utf8(out).unwrap()
+}
+
+// the digits of `n` in `base`, most significant first
+fn digits(mut n: u64, base: u64) -> Vec<usize> {
+    let mut digits = Vec::new();
+    while n > 0 {
+        digits.push((n % base) as usize);
+        n /= base;
+    }
+    digits.reverse();
+    digits
+}
+
+// Horner's method: the first digit, then for each one after it `base *`
+// and, unless it's 0, `digit +`
+fn horner_cost(table: &Table, n: u64, base: u64) -> usize {
+    let digits = digits(n, base);
+    let rest: usize = digits[1..]
+        .iter()
+        .map(|d| match d {
+            0 => 0,
+            d => table.cost[*d] + 1,
+        })
+        .sum();
+    table.cost[digits[0]] + (digits.len() - 1) * (table.cost[base as usize] + 1) + rest
+}
+
+fn horner(table: &Table, n: u64, base: u64, out: &mut Vec<u8>) {
+    let digits = digits(n, base);
+    table.write(digits[0], out);
+    for digit in digits[1..].iter() {
+        table.write(base as usize, out);
+        out.push(b'*');
+        if *digit > 0 {
+            table.write(*digit, out);
+            out.push(b'+');
+        }
+    }
+}
+
+// The cells that push each byte of `text` in turn, so the last ends up on
+// top
+pub fn string(text: &[u8], dialect: Dialect) -> String {
+    #[derive(Clone, Copy)]
+    enum How {
+        Quoted,    // in string mode
+        Number,    // as a number, outside string mode
+        Duplicate, // with `:`, the same as the character before
+    }
+
+    // Funge-98 may push a run of spaces in string mode as a single space
+    let can_quote = |i: usize, in_string: bool| {
+        let c = text[i];
+        (c == b' ' || c.is_ascii_graphic())
+            && c != b'"'
+            && !(dialect == Dialect::Befunge98
+                && c == b' '
+                && in_string
+                && i > 0
+                && text[i - 1] == b' ')
+    };
+
+    // best[i][s] is the cheapest way to push the first i bytes, ending in
+    // string mode if s is 1, with how the last byte was pushed and the
+    // state before it
+    const OUT: usize = 0;
+    const IN: usize = 1;
+    let mut best: Vec<[Option<(usize, How, usize)>; 2]> = vec![[None, None]; text.len() + 1];
+    best[0][OUT] = Some((0, How::Number, OUT));
+    let consider = |slot: &mut Option<(usize, How, usize)>, cost, how, from| {
+        if slot.is_none_or(|(c, _, _)| cost < c) {
+            *slot = Some((cost, how, from));
+        }
+    };
+
+    for i in 0..text.len() {
+        for from in [OUT, IN] {
+            let (cost, _, _) = match best[i][from] {
+                Some(best) => best,
+                None => continue,
+            };
+            if can_quote(i, from == IN) {
+                // opening the string takes a `"`
+                let open = (from == OUT) as usize;
+                consider(&mut best[i + 1][IN], cost + open + 1, How::Quoted, from);
+            }
+
+            // anything else has to close the string first
+            let close = (from == IN) as usize;
+            let cost = cost + close;
+            let pushed = number(text[i] as i64, dialect).len();
+            consider(&mut best[i + 1][OUT], cost + pushed, How::Number, from);
+            if i > 0 && text[i - 1] == text[i] {
+                consider(&mut best[i + 1][OUT], cost + 1, How::Duplicate, from);
+            }
+        }
+    }
+
+    // walk back through the choices, then write them out in order
+    let mut choices = Vec::new();
+    let mut state = match (best[text.len()][OUT], best[text.len()][IN]) {
+        (Some((out, _, _)), Some((quoted, _, _))) if quoted + 1 < out => IN,
+        (None, Some(_)) => IN,
+        _ => OUT,
+    };
+    for i in (0..text.len()).rev() {
+        let (_, how, from) = best[i + 1][state].unwrap();
+        choices.push(how);
+        state = from;
+    }
+    choices.reverse();
+
+    let mut out = String::new();
+    let mut in_string = false;
+    for (i, how) in choices.into_iter().enumerate() {
+        if in_string != matches!(how, How::Quoted) {
+            out.push('"');
+            in_string = !in_string;
+        }
+        match how {
+            How::Quoted => out.push(text[i] as char),
+            How::Number => out.push_str(&number(text[i] as i64, dialect)),
+            How::Duplicate => out.push(':'),
+        }
+    }
+    if in_string {
+        out.push('"');
+    }
+    out
+}
+
+// The stack left by running `code` on an empty stack, or None if it uses
+// anything but digits, string mode and `+ - * / % : \`
+pub fn evaluate(code: &str, dialect: Dialect) -> Option<Vec<i64>> {
+    let mut stack = Stack::<usize>::new();
+    let mut in_string = false;
+    for c in code.chars() {
+        match (in_string, c) {
+            (_, '"') => in_string = !in_string,
+            (true, c) => stack.push(c as usize),
+            (false, '0'..='9') => stack.push(c as usize - '0' as usize),
+            (false, 'a'..='f') if dialect == Dialect::Befunge98 => {
+                stack.push(c as usize - 'a' as usize + 10)
+            }
+            (false, '+') => stack.apply(NAry::<usize, 2>::add()).ok()?,
+            (false, '-') => stack.apply(NAry::<usize, 2>::sub()).ok()?,
+            (false, '*') => stack.apply(NAry::<usize, 2>::mul()).ok()?,
+            (false, '/') => stack.apply(NAry::<usize, 2>::div()).ok()?,
+            (false, '%') => stack.apply(NAry::<usize, 2>::rem()).ok()?,
+            (false, ':') => stack.dupe(),
+            (false, '\\') => stack.swap(),
+            _ => return None,
+        }
+    }
+    Some(stack.as_slice().iter().map(|v| *v as i64).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: [Dialect; 2] = [Dialect::Befunge93, Dialect::Befunge98];
+
+    #[test]
+    fn numbers_evaluate_to_themselves() {
+        let samples = (-300..=300)
+            .chain([4095, 4096, 4097, 65536, 1_000_003, -1_000_003])
+            .chain([i64::MAX, i64::MIN, i64::MIN + 1]);
+        for n in samples {
+            for dialect in BOTH {
+                let code = number(n, dialect);
+                assert_eq!(evaluate(&code, dialect), Some(vec![n]), "{} as {}", n, code);
+            }
+        }
+    }
+
+    #[test]
+    fn numbers_are_short() {
+        assert_eq!(number(7, Dialect::Befunge98), "7");
+        assert_eq!(number(15, Dialect::Befunge98), "f");
+        assert_eq!(number(15, Dialect::Befunge93).len(), 3);
+        assert_eq!(number(81, Dialect::Befunge93), "9:*");
+        assert_eq!(number(-1, Dialect::Befunge98), "01-");
+        assert_eq!(number(4096, Dialect::Befunge98), "8:*:*");
+    }
+
+    #[test]
+    fn strings_evaluate_to_their_bytes() {
+        let samples: [&[u8]; 5] = [b"Hello, World!", b"say \"hi\"\n", b"a  b", b"", b"\n\n\n\n"];
+        for text in samples {
+            for dialect in BOTH {
+                let code = string(text, dialect);
+                let bytes: Vec<i64> = text.iter().map(|b| *b as i64).collect();
+                assert_eq!(
+                    evaluate(&code, dialect),
+                    Some(bytes),
+                    "{:?} as {}",
+                    text,
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn strings_leave_string_mode_when_it_pays() {
+        assert_eq!(string(b"Hi!", Dialect::Befunge98), "\"Hi!\"");
+        assert_eq!(string(b"a\"b", Dialect::Befunge98), "\"a\"2f+:+7e*");
+        assert!(string(b"\x7f\x7f\x7f", Dialect::Befunge98).ends_with("::"));
+        assert_eq!(string(b"a  bc", Dialect::Befunge98), "\"a \":\"bc\"");
+        assert_eq!(string(b"a  bc", Dialect::Befunge93), "\"a  bc\"");
+    }
+}
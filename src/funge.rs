@@ -1,9 +1,37 @@
-use rand::{rngs::ThreadRng, Rng};
-use std::io::{self, Read};
+use crate::coverage::Coverage;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[allow(dead_code)]
 pub mod code {
-    use super::Direction;
+    use super::{Dialect, Direction};
+
+    // What an instruction is for, used to colour and describe source code
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Category {
+        Literal,     // pushes a value
+        Flow,        // no-ops, stopping, skipping and string mode
+        Movement,    // changes the direction of the IP
+        Stack,       // rearranges the stack
+        Arithmetic,  // arithmetic and logic
+        Io,          // reads input or writes output
+        Memory,      // reads or writes funge space
+        System,      // files, commands, system information and custom instructions
+        Fingerprint, // loads, unloads or runs fingerprint semantics
+    }
 
     #[derive(Debug)]
     pub enum Instruction {
@@ -13,13 +41,15 @@ pub mod code {
         // special instructions
         NoOp,       // no operation
         Stop,       // stop execution
+        Quit,       // pop an exit status and stop execution
         Skip,       // skip the next Instruction
-        StringMode, // skip the next Instruction
+        StringMode, // toggle string mode, in which every cell pushes its own value
+        Reflect,    // reverse the direction of the instruction pointer
 
         // Instruction Pointer Movement instructions
         Move(Direction),  // move in a specific direction
         MoveEastOrWest, // pop a value off the stack and move east if the value is 0, west otherwise
-        MoveNorthOrSouth, // pop a value off the stack and move north if the value is 0, south otherwise
+        MoveNorthOrSouth, // pop a value off the stack and move south if the value is 0, north otherwise
         MoveRandom,       // move in a random direction
 
         // Stack Manipulation instructions
@@ -28,18 +58,19 @@ pub mod code {
         Pop,       // pop the top value off the stack
 
         // Arithmetic instructions
-        Add, // pop a and b, push a + b
-        Sub, // pop a and b, push a - b
-        Mul, // pop a and b, push a * b
-        Div, // pop a and b, push a / b
-        Mod, // pop a and b, push a % b
+        // a is the top of the stack and b the value beneath it
+        Add, // pop a and b, push b + a
+        Sub, // pop a and b, push b - a
+        Mul, // pop a and b, push b * a
+        Div, // pop a and b, push b / a, or 0 if a is 0
+        Mod, // pop a and b, push b % a, or 0 if a is 0
 
         // Logical instructions
-        GreaterThan, // pop a and b, push 1 if a > b, 0 otherwise
+        GreaterThan, // pop a and b, push 1 if b > a, 0 otherwise
         Not,         // pop a push 1 if a == 0, 0 otherwise
 
         // I/O instructions
-        PrintInt, // pop a, print a as an integer
+        PrintInt, // pop a, print a as an integer followed by a space
         PrintChr, // pop a, print a as a character
         ReadInt,  // read an integer from stdin, push it
         ReadChr,  // read a character from stdin, push its ascii value
@@ -47,17 +78,74 @@ pub mod code {
         // Put and Get instructions
         Put, // pop y, x and v, put v at (x, y)
         Get, // pop y and x, push the value at (x, y)
+
+        // File I/O instructions
+        InputFile, // pop a filename, flags and a vector, load the file there, push its size and the vector
+        OutputFile, // pop a filename, flags, a vector and a size, write that region of space to the file
+
+        // System instructions
+        Execute, // pop a command, run it if the exec policy allows, push its exit status
+        SysInfo, // pop n, push system information (or only its nth cell if n > 0)
+
+        // Fingerprint instructions
+        LoadSemantics,   // pop a fingerprint, bind its semantics to A-Z
+        UnloadSemantics, // pop a fingerprint, unbind its semantics from A-Z
+        Semantic(char),  // run whatever semantics are bound to this letter
+
+        // Custom instructions
+        Custom(char), // run the instruction an embedder registered for this character
     }
 
     impl Instruction {
-        pub fn from_raw(raw: usize, string_mode: &bool) -> Self {
+        pub fn from_raw(raw: usize, string_mode: &bool, dialect: &Dialect) -> Self {
+            Self::from_raw_with(raw, string_mode, dialect, |_| false)
+        }
+
+        // as from_raw, but the characters `custom` claims are Custom ahead of
+        // the built-in table. String mode pushes them like any other.
+        pub fn from_raw_with(
+            raw: usize,
+            string_mode: &bool,
+            dialect: &Dialect,
+            custom: impl Fn(char) -> bool,
+        ) -> Self {
             if *string_mode {
                 return match (raw as u8) as char {
                     '"' => Self::StringMode,
                     x => Self::ReadAndPush(x as usize),
                 };
             }
+            if custom((raw as u8) as char) {
+                return Self::Custom((raw as u8) as char);
+            }
+
+            match dialect {
+                Dialect::Befunge93 => Self::from_raw_93(raw),
+                Dialect::Befunge98 => Self::from_raw_98(raw),
+            }
+        }
+
+        fn from_raw_98(raw: usize) -> Self {
+            match (raw as u8) as char {
+                'r' => Self::Reflect,
+                'q' => Self::Quit,
+                'i' => Self::InputFile,
+                'o' => Self::OutputFile,
+                '=' => Self::Execute,
+                'y' => Self::SysInfo,
+                '(' => Self::LoadSemantics,
+                ')' => Self::UnloadSemantics,
+                x @ 'A'..='Z' => Self::Semantic(x),
+                x @ 'a'..='f' => Self::ReadAndPush(x as usize - 'a' as usize + 10),
+                _ => match Self::from_raw_93(raw) {
+                    // unknown instructions reflect in Funge-98
+                    Self::ReadAndPush(_) if !(raw as u8).is_ascii_digit() => Self::Reflect,
+                    instruction => instruction,
+                },
+            }
+        }
 
+        fn from_raw_93(raw: usize) -> Self {
             match (raw as u8) as char {
                 ' ' => Self::NoOp,
                 '@' => Self::Stop,
@@ -92,19 +180,207 @@ pub mod code {
                 _ => Self::ReadAndPush(raw as usize),
             }
         }
+
+        pub fn category(&self) -> Category {
+            match self {
+                Self::ReadAndPush(_) => Category::Literal,
+                Self::NoOp | Self::Stop | Self::Quit | Self::Skip | Self::StringMode => {
+                    Category::Flow
+                }
+                Self::Reflect
+                | Self::Move(_)
+                | Self::MoveEastOrWest
+                | Self::MoveNorthOrSouth
+                | Self::MoveRandom => Category::Movement,
+                Self::Duplicate | Self::Swap | Self::Pop => Category::Stack,
+                Self::Add
+                | Self::Sub
+                | Self::Mul
+                | Self::Div
+                | Self::Mod
+                | Self::GreaterThan
+                | Self::Not => Category::Arithmetic,
+                Self::PrintInt | Self::PrintChr | Self::ReadInt | Self::ReadChr => Category::Io,
+                Self::Put | Self::Get => Category::Memory,
+                Self::InputFile
+                | Self::OutputFile
+                | Self::Execute
+                | Self::SysInfo
+                | Self::Custom(_) => Category::System,
+                Self::LoadSemantics | Self::UnloadSemantics | Self::Semantic(_) => {
+                    Category::Fingerprint
+                }
+            }
+        }
+
+        // a one line description of what the instruction does, where a is
+        // the top of the stack and b the value beneath it
+        pub fn describe(&self) -> String {
+            let text = match self {
+                Self::ReadAndPush(value) => return format!("push {}", value),
+                Self::Move(direction) => {
+                    return format!("move {}", format!("{:?}", direction).to_lowercase())
+                }
+                Self::Semantic(letter) => {
+                    return format!("run the semantics a fingerprint bound to {}", letter)
+                }
+                Self::Custom(c) => return format!("run the custom instruction {:?}", c),
+                Self::NoOp => "no operation",
+                Self::Stop => "stop execution",
+                Self::Quit => "pop an exit status and stop execution",
+                Self::Skip => "skip the next cell",
+                Self::StringMode => "toggle string mode, in which every cell pushes its own value",
+                Self::Reflect => "reverse the direction of the IP",
+                Self::MoveEastOrWest => "pop a value, move east if it is 0 and west otherwise",
+                Self::MoveNorthOrSouth => "pop a value, move south if it is 0 and north otherwise",
+                Self::MoveRandom => "move in a random direction",
+                Self::Duplicate => "duplicate the top value on the stack",
+                Self::Swap => "swap the top two values on the stack",
+                Self::Pop => "pop the top value off the stack",
+                Self::Add => "pop a and b, push b + a",
+                Self::Sub => "pop a and b, push b - a",
+                Self::Mul => "pop a and b, push b * a",
+                Self::Div => "pop a and b, push b / a, or 0 if a is 0",
+                Self::Mod => "pop a and b, push b % a, or 0 if a is 0",
+                Self::GreaterThan => "pop a and b, push 1 if b > a and 0 otherwise",
+                Self::Not => "pop a, push 1 if a is 0 and 0 otherwise",
+                Self::PrintInt => "pop a, print it as an integer followed by a space",
+                Self::PrintChr => "pop a, print it as a character",
+                Self::ReadInt => "read an integer from input and push it",
+                Self::ReadChr => "read a character from input and push its value",
+                Self::Put => "pop y, x and v, put v at (x, y)",
+                Self::Get => "pop y and x, push the value at (x, y)",
+                Self::InputFile => "pop a file name, flags and a vector, load the file there",
+                Self::OutputFile => {
+                    "pop a file name, flags, a vector and a size, write that region to the file"
+                }
+                Self::Execute => "pop a command, run it and push its exit status",
+                Self::SysInfo => "pop n, push system information, or only its nth cell",
+                Self::LoadSemantics => "pop a fingerprint, bind its semantics to A-Z",
+                Self::UnloadSemantics => "pop a fingerprint, unbind its semantics from A-Z",
+            };
+            text.to_string()
+        }
+    }
+}
+
+// Fingerprints are named extensions that `(` binds to the letters A-Z.
+pub mod fingerprint {
+    // What a letter does while a fingerprint binds it, run by Vm::semantic
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Semantic {
+        Reflect,
+        Push(i64),
+        And, // pop a and b, push b & a
+        Or,  // pop a and b, push b | a
+        Xor, // pop a and b, push b ^ a
+        Not, // pop a, push !a
+    }
+
+    pub struct Fingerprint {
+        pub name: &'static str,
+        pub semantics: &'static [(char, Semantic)],
+    }
+
+    impl Fingerprint {
+        // the id `(` expects, the name's bytes read as a base 256 number
+        pub fn id(&self) -> usize {
+            self.name
+                .bytes()
+                .fold(0, |id, b| id.wrapping_mul(256).wrapping_add(b as usize))
+        }
+    }
+
+    pub const BUILT_IN: [Fingerprint; 3] = [NULL, ROMA, BOOL];
+
+    // an id is one cell per byte of a usize, so no count `(` pops is longer
+    pub const MAX_ID_CELLS: usize = std::mem::size_of::<usize>();
+
+    pub fn find(id: usize) -> Option<&'static Fingerprint> {
+        BUILT_IN.iter().find(|f| f.id() == id)
     }
+
+    // every letter reflects
+    pub const NULL: Fingerprint = Fingerprint {
+        name: "NULL",
+        semantics: &[
+            ('A', Semantic::Reflect),
+            ('B', Semantic::Reflect),
+            ('C', Semantic::Reflect),
+            ('D', Semantic::Reflect),
+            ('E', Semantic::Reflect),
+            ('F', Semantic::Reflect),
+            ('G', Semantic::Reflect),
+            ('H', Semantic::Reflect),
+            ('I', Semantic::Reflect),
+            ('J', Semantic::Reflect),
+            ('K', Semantic::Reflect),
+            ('L', Semantic::Reflect),
+            ('M', Semantic::Reflect),
+            ('N', Semantic::Reflect),
+            ('O', Semantic::Reflect),
+            ('P', Semantic::Reflect),
+            ('Q', Semantic::Reflect),
+            ('R', Semantic::Reflect),
+            ('S', Semantic::Reflect),
+            ('T', Semantic::Reflect),
+            ('U', Semantic::Reflect),
+            ('V', Semantic::Reflect),
+            ('W', Semantic::Reflect),
+            ('X', Semantic::Reflect),
+            ('Y', Semantic::Reflect),
+            ('Z', Semantic::Reflect),
+        ],
+    };
+
+    // roman numerals push their value
+    pub const ROMA: Fingerprint = Fingerprint {
+        name: "ROMA",
+        semantics: &[
+            ('C', Semantic::Push(100)),
+            ('D', Semantic::Push(500)),
+            ('I', Semantic::Push(1)),
+            ('L', Semantic::Push(50)),
+            ('M', Semantic::Push(1000)),
+            ('V', Semantic::Push(5)),
+            ('X', Semantic::Push(10)),
+        ],
+    };
+
+    // bitwise logic
+    pub const BOOL: Fingerprint = Fingerprint {
+        name: "BOOL",
+        semantics: &[
+            ('A', Semantic::And),
+            ('N', Semantic::Not),
+            ('O', Semantic::Or),
+            ('X', Semantic::Xor),
+        ],
+    };
 }
 
 pub mod ops {
-    pub struct NAry<'a, T: num_traits::PrimInt, const N: usize>(&'a dyn Fn([T; N]) -> T);
+    use super::{Cell, Overflow};
+
+    // An operator and what it does when its result doesn't fit in a cell,
+    // wrapping unless told otherwise
+    pub struct NAry<'a, T: Cell, const N: usize>(
+        &'a dyn Fn([T; N], Overflow) -> Option<T>,
+        Overflow,
+    );
+
+    impl<'a, T: Cell, const N: usize> NAry<'a, T, N> {
+        // None if the result overflowed and the policy is to trap
+        pub fn eval(&self, args: [T; N]) -> Option<T> {
+            self.0(args, self.1)
+        }
 
-    impl<'a, T: num_traits::PrimInt, const N: usize> NAry<'a, T, N> {
-        pub fn eval(&self, args: [T; N]) -> T {
-            self.0(args)
+        pub fn new(op: &'a dyn Fn([T; N], Overflow) -> Option<T>) -> NAry<'a, T, N> {
+            NAry(op, Overflow::Wrap)
         }
 
-        pub fn new(op: &'a dyn Fn([T; N]) -> T) -> NAry<'a, T, N> {
-            NAry(op)
+        pub fn overflow(self, overflow: Overflow) -> NAry<'a, T, N> {
+            NAry(self.0, overflow)
         }
 
         // binary operator constructors
@@ -133,58 +409,292 @@ pub mod ops {
         }
     }
 
-    fn _add<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        terms[0] + terms[1]
+    // arithmetic works on the signed value of a cell
+    fn _add<T: Cell>(terms: [T; 2], overflow: Overflow) -> Option<T> {
+        terms[0].add(&terms[1], overflow)
     }
-    fn _sub<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        terms[0] - terms[1]
+    fn _sub<T: Cell>(terms: [T; 2], overflow: Overflow) -> Option<T> {
+        terms[0].sub(&terms[1], overflow)
     }
-    fn _times<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        terms[0] * terms[1]
+    fn _times<T: Cell>(terms: [T; 2], overflow: Overflow) -> Option<T> {
+        terms[0].mul(&terms[1], overflow)
     }
-    fn _divide<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        terms[0] / terms[1]
-    }
-    fn _mod<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        terms[0] % terms[1]
+    fn _divide<T: Cell>(terms: [T; 2], overflow: Overflow) -> Option<T> {
+        match terms[1].is_zero() {
+            true => Some(T::from_i64(0)),
+            false => terms[0].div(&terms[1], overflow),
+        }
     }
-    fn _gt<T: num_traits::PrimInt>(terms: [T; 2]) -> T {
-        match terms[0] > terms[1] {
-            true => T::one(),
-            false => T::zero(),
+    // the remainder always fits, even of the smallest cell by -1
+    fn _mod<T: Cell>(terms: [T; 2], _: Overflow) -> Option<T> {
+        match terms[1].is_zero() {
+            true => Some(T::from_i64(0)),
+            false => Some(terms[0].rem(&terms[1])),
         }
     }
-    fn _not<T: num_traits::PrimInt>(terms: [T; 1]) -> T {
-        if !terms[0].is_zero() {
-            T::zero()
-        } else {
-            T::one()
+    fn _gt<T: Cell>(terms: [T; 2], _: Overflow) -> Option<T> {
+        Some(T::from_i64(terms[0].gt(&terms[1]) as i64))
+    }
+    fn _not<T: Cell>(terms: [T; 1], _: Overflow) -> Option<T> {
+        Some(T::from_i64(terms[0].is_zero() as i64))
+    }
+}
+
+// What happens when a result doesn't fit in a fixed width cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    #[default]
+    Wrap, // keep the low bits
+    Saturate, // clamp to the smallest or largest cell
+    Trap,     // stop with VmError::Overflow
+}
+
+// What the stack and funge space hold. Fixed width cells are two's
+// complement integers, whether or not the type is signed, that overflow as
+// the Overflow policy says. BigInt cells never overflow.
+pub trait Cell: Clone + fmt::Debug + PartialEq + Eq + Hash + 'static {
+    // bytes per cell as `y` reports them, 0 for no limit
+    const SIZE: usize;
+
+    // wraps values that don't fit
+    fn from_i64(value: i64) -> Self;
+    // None if `value` doesn't fit and the policy is to trap
+    fn fit(value: i64, overflow: Overflow) -> Option<Self>;
+    // the low 64 bits, read as two's complement
+    fn as_i64(&self) -> i64;
+    // a run of ASCII digits, saturating at the largest cell if fixed width
+    fn from_decimal(digits: &[u8]) -> Self;
+    // the signed value in decimal
+    fn to_decimal(&self) -> String;
+
+    fn is_zero(&self) -> bool;
+    fn add(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    fn sub(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    // rounds toward zero, `other` isn't 0
+    fn div(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    // takes the sign of self, `other` isn't 0
+    fn rem(&self, other: &Self) -> Self;
+    fn gt(&self, other: &Self) -> bool;
+    fn and(&self, other: &Self) -> Self;
+    fn or(&self, other: &Self) -> Self;
+    fn xor(&self, other: &Self) -> Self;
+    fn not(&self) -> Self;
+}
+
+// applies a signed operator to two fixed width cells under a policy
+macro_rules! signed_op {
+    ($signed:ty, $cell:ty, $a:expr, $b:expr, $overflow:expr,
+     $wrapping:ident, $saturating:ident, $checked:ident) => {{
+        let (a, b) = (*$a as $signed, *$b as $signed);
+        match $overflow {
+            Overflow::Wrap => Some(a.$wrapping(b)),
+            Overflow::Saturate => Some(a.$saturating(b)),
+            Overflow::Trap => a.$checked(b),
+        }
+        .map(|v| v as $cell)
+    }};
+}
+
+macro_rules! fixed_width_cell {
+    ($($cell:ty as $signed:ty),* $(,)?) => {$(
+        impl Cell for $cell {
+            const SIZE: usize = std::mem::size_of::<$cell>();
+
+            fn from_i64(value: i64) -> Self {
+                value as $signed as $cell
+            }
+            fn fit(value: i64, overflow: Overflow) -> Option<Self> {
+                match (<$signed>::try_from(value), overflow) {
+                    (Ok(v), _) => Some(v as $cell),
+                    (Err(_), Overflow::Wrap) => Some(Self::from_i64(value)),
+                    (Err(_), Overflow::Saturate) if value < 0 => Some(<$signed>::MIN as $cell),
+                    (Err(_), Overflow::Saturate) => Some(<$signed>::MAX as $cell),
+                    (Err(_), Overflow::Trap) => None,
+                }
+            }
+            fn as_i64(&self) -> i64 {
+                *self as $signed as i64
+            }
+            fn from_decimal(digits: &[u8]) -> Self {
+                digits.iter().fold(0 as $signed, |value, digit| {
+                    value.saturating_mul(10).saturating_add((digit - b'0') as $signed)
+                }) as $cell
+            }
+            fn to_decimal(&self) -> String {
+                (*self as $signed).to_string()
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+            fn add(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                signed_op!($signed, $cell, self, other, overflow,
+                    wrapping_add, saturating_add, checked_add)
+            }
+            fn sub(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                signed_op!($signed, $cell, self, other, overflow,
+                    wrapping_sub, saturating_sub, checked_sub)
+            }
+            fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                signed_op!($signed, $cell, self, other, overflow,
+                    wrapping_mul, saturating_mul, checked_mul)
+            }
+            fn div(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                signed_op!($signed, $cell, self, other, overflow,
+                    wrapping_div, saturating_div, checked_div)
+            }
+            fn rem(&self, other: &Self) -> Self {
+                (*self as $signed).wrapping_rem(*other as $signed) as $cell
+            }
+            fn gt(&self, other: &Self) -> bool {
+                *self as $signed > *other as $signed
+            }
+            fn and(&self, other: &Self) -> Self {
+                self & other
+            }
+            fn or(&self, other: &Self) -> Self {
+                self | other
+            }
+            fn xor(&self, other: &Self) -> Self {
+                self ^ other
+            }
+            fn not(&self) -> Self {
+                !self
+            }
         }
+    )*};
+}
+
+fixed_width_cell!(
+    usize as isize,
+    u8 as i8,
+    u16 as i16,
+    u32 as i32,
+    u64 as i64,
+    u128 as i128,
+    isize as isize,
+    i8 as i8,
+    i16 as i16,
+    i32 as i32,
+    i64 as i64,
+    i128 as i128,
+);
+
+impl Cell for BigInt {
+    const SIZE: usize = 0;
+
+    fn from_i64(value: i64) -> Self {
+        BigInt::from(value)
+    }
+    fn fit(value: i64, _: Overflow) -> Option<Self> {
+        Some(BigInt::from(value))
+    }
+    fn as_i64(&self) -> i64 {
+        ToPrimitive::to_i64(self).unwrap_or_else(|| {
+            let low = self & BigInt::from(u64::MAX);
+            low.to_u64().unwrap_or(0) as i64
+        })
+    }
+    fn from_decimal(digits: &[u8]) -> Self {
+        BigInt::parse_bytes(digits, 10).unwrap_or_default()
+    }
+    fn to_decimal(&self) -> String {
+        self.to_string()
+    }
+
+    fn is_zero(&self) -> bool {
+        num_traits::Zero::is_zero(self)
+    }
+    fn add(&self, other: &Self, _: Overflow) -> Option<Self> {
+        Some(self + other)
+    }
+    fn sub(&self, other: &Self, _: Overflow) -> Option<Self> {
+        Some(self - other)
+    }
+    fn mul(&self, other: &Self, _: Overflow) -> Option<Self> {
+        Some(self * other)
+    }
+    fn div(&self, other: &Self, _: Overflow) -> Option<Self> {
+        Some(self / other)
+    }
+    fn rem(&self, other: &Self) -> Self {
+        self % other
+    }
+    fn gt(&self, other: &Self) -> bool {
+        self > other
+    }
+    fn and(&self, other: &Self) -> Self {
+        self & other
     }
+    fn or(&self, other: &Self) -> Self {
+        self | other
+    }
+    fn xor(&self, other: &Self) -> Self {
+        self ^ other
+    }
+    fn not(&self) -> Self {
+        !self
+    }
+}
+
+// the cell as a byte, if it is one. A cell only a byte wide always is
+fn byte<T: Cell>(value: &T) -> Option<u8> {
+    let b = value.as_i64() as u8;
+    (T::from_i64(b as i64) == *value).then_some(b)
 }
 
 #[derive(Debug)]
-pub struct Stack<T: num_traits::PrimInt>(Vec<T>);
+pub struct Stack<T: Cell>(Vec<T>);
 
-impl<T: num_traits::PrimInt> Stack<T> {
-    fn new() -> Stack<T> {
+impl<T: Cell> Stack<T> {
+    pub(crate) fn new() -> Stack<T> {
         Stack(Vec::<T>::new())
     }
 
-    fn apply<const N: usize>(&mut self, op: ops::NAry<T, N>) -> () {
-        let mut args: [T; N] = [T::zero(); N];
-        for i in 0..N {
-            args[i] = self.pop();
-        }
+    // the cells from the bottom of the stack to the top
+    pub fn as_slice(&self) -> &[T] {
+        &self.0
+    }
+}
+
+// converts a signed value to a cell, negative values wrap around for unsigned
+// cells at the cell's width (usize is 32 bits on wasm32)
+pub(crate) fn cell<T: Cell>(value: i64) -> T {
+    T::from_i64(value)
+}
+
+// the inverse of `cell`
+pub(crate) fn signed<T: Cell>(value: T) -> i64 {
+    value.as_i64()
+}
+
+// `value` with a decimal digit written after it, or None if that doesn't
+// fit and the policy is to trap
+fn append_digit<T: Cell>(value: &T, digit: u8, overflow: Overflow) -> Option<T> {
+    let shifted = value.mul(&T::from_i64(10), overflow)?;
+    shifted.add(&T::from_i64((digit - b'0') as i64), overflow)
+}
+
+// A stack of cells, with everything beyond pushing and popping built on
+// those two
+pub(crate) trait Lifo<T: Cell> {
+    fn push(&mut self, item: T);
+    fn pop(&mut self) -> T;
+
+    fn apply<const N: usize>(&mut self, op: ops::NAry<T, N>) -> Result<(), VmError> {
+        let mut args: [T; N] = std::array::from_fn(|_| self.pop());
+        // the top of the stack is the last argument
+        args.reverse();
 
-        let result = op.eval(args);
+        let result = op.eval(args).ok_or(VmError::Overflow(T::SIZE * 8))?;
         self.push(result);
-        ()
+        Ok(())
     }
 
     fn dupe(&mut self) -> () {
         let item = self.pop();
-        let duplicate = item;
+        let duplicate = item.clone();
         self.push(item);
         self.push(duplicate);
         ()
@@ -193,18 +703,38 @@ impl<T: num_traits::PrimInt> Stack<T> {
     fn swap(&mut self) -> () {
         let item1 = self.pop();
         let item2 = self.pop();
-        self.push(item2);
         self.push(item1);
+        self.push(item2);
         ()
     }
-}
 
-trait Lifo<T> {
-    fn push(&mut self, item: T);
-    fn pop(&mut self) -> T;
+    // pops a 0gnirts, i.e. characters up to and including a terminating zero
+    fn pop_string(&mut self) -> String {
+        let mut bytes = Vec::<u8>::new();
+        loop {
+            match byte(&self.pop()) {
+                Some(0) => break,
+                Some(b) => bytes.push(b),
+                None => bytes.push(b'?'),
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // vectors are pushed x first, so y comes off the stack first
+    fn pop_vector(&mut self) -> Location {
+        let y = signed(self.pop());
+        let x = signed(self.pop());
+        Location(x, y)
+    }
+
+    fn push_vector(&mut self, vector: &Location) {
+        self.push(cell(vector.0));
+        self.push(cell(vector.1));
+    }
 }
 
-impl<T: num_traits::PrimInt> Lifo<T> for Stack<T> {
+impl<T: Cell> Lifo<T> for Stack<T> {
     fn push(&mut self, item: T) -> () {
         self.0.push(item);
         ()
@@ -213,12 +743,162 @@ impl<T: num_traits::PrimInt> Lifo<T> for Stack<T> {
     fn pop(&mut self) -> T {
         match self.0.pop() {
             Some(x) => x,
-            _ => T::zero(),
+            _ => T::from_i64(0),
         }
     }
 }
 
-#[derive(Debug)]
+// Hooks a Vm calls as it runs, so that tracers, profilers and debuggers can
+// watch it from outside. Every hook does nothing unless overridden, and a
+// Vm built without an observer has `()`, whose calls compile away.
+pub trait Observer<C: Cell> {
+    // the IP is at `at`, about to run what's there
+    fn tick(&mut self, _at: &Location) {}
+    // the cell under the IP was read as `instruction`
+    fn decode(&mut self, _at: &Location, _instruction: &code::Instruction) {}
+    fn push(&mut self, _value: &C) {}
+    fn pop(&mut self, _value: &C) {} // 0 when the stack was empty
+    fn read(&mut self, _at: &Location, _value: &C) {} // by `g`
+    fn write(&mut self, _at: &Location, _value: &C) {} // by `p` or `i`
+    fn output(&mut self, _bytes: &[u8]) {}
+    // the IP has moved on to `to`, heading `delta`
+    fn moved(&mut self, _to: &Location, _delta: Direction) {}
+}
+
+impl<C: Cell> Observer<C> for () {}
+
+// The Vm's stack, telling its observer about every push and pop
+struct ObservedStack<'a, C: Cell, O: Observer<C>> {
+    stack: &'a mut Stack<C>,
+    observer: &'a mut O,
+}
+
+impl<C: Cell, O: Observer<C>> Lifo<C> for ObservedStack<'_, C, O> {
+    fn push(&mut self, item: C) {
+        self.observer.push(&item);
+        self.stack.push(item);
+    }
+
+    fn pop(&mut self) -> C {
+        let item = self.stack.pop();
+        self.observer.pop(&item);
+        item
+    }
+}
+
+// An instruction an embedder adds to the interpreter
+pub type CustomInstruction<C> = dyn Fn(&mut VmContext<C>) -> Result<(), VmError>;
+
+// Custom instructions by character, each taking the place of any built-in
+// instruction on it. Cells are decoded by their low byte, so only
+// characters up to U+00FF can ever run.
+pub struct Registry<C: Cell = usize> {
+    instructions: HashMap<char, Box<CustomInstruction<C>>>,
+}
+
+impl<C: Cell> Default for Registry<C> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl<C: Cell> fmt::Debug for Registry<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.instructions.keys()).finish()
+    }
+}
+
+impl<C: Cell> Registry<C> {
+    pub fn new() -> Registry<C> {
+        Registry {
+            instructions: HashMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        c: char,
+        instruction: impl Fn(&mut VmContext<C>) -> Result<(), VmError> + 'static,
+    ) -> &mut Registry<C> {
+        self.instructions.insert(c, Box::new(instruction));
+        self
+    }
+
+    pub fn unregister(&mut self, c: char) {
+        self.instructions.remove(&c);
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.instructions.contains_key(&c)
+    }
+}
+
+// What a custom instruction can see and change of the Vm running it. Its
+// pushes, pops, reads and writes reach the Vm's observer like any other.
+pub struct VmContext<'a, C: Cell = usize> {
+    stack: &'a mut Stack<C>,
+    space: &'a mut Space<C>,
+    location: &'a mut Location,
+    delta: &'a mut Direction,
+    observer: &'a mut dyn Observer<C>,
+}
+
+impl<C: Cell> VmContext<'_, C> {
+    pub fn push(&mut self, value: C) {
+        self.observer.push(&value);
+        self.stack.push(value);
+    }
+
+    // 0 when the stack is empty
+    pub fn pop(&mut self) -> C {
+        let value = self.stack.pop();
+        self.observer.pop(&value);
+        value
+    }
+
+    pub fn get(&mut self, at: &Location) -> C {
+        let value = self.space.get(at);
+        self.observer.read(at, &value);
+        value
+    }
+
+    // writing past the right or bottom edge grows the space, as `p` does
+    pub fn set(&mut self, value: C, at: Location) -> Result<(), VmError> {
+        self.observer.write(&at, &value);
+        self.space.set(value, at)
+    }
+
+    pub fn location(&self) -> Location {
+        Location(self.location.0, self.location.1)
+    }
+
+    // the IP moves on from `at` once the instruction is done, so the next
+    // instruction to run is the one after it
+    pub fn jump(&mut self, at: Location) {
+        *self.location = at;
+    }
+
+    pub fn delta(&self) -> Direction {
+        *self.delta
+    }
+
+    pub fn turn(&mut self, delta: Direction) {
+        *self.delta = delta;
+    }
+
+    pub fn reflect(&mut self) {
+        *self.delta = self.delta.reverse();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    Befunge93,
+    #[default]
+    Befunge98,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     North,
     South,
@@ -226,67 +906,274 @@ pub enum Direction {
     West,
 }
 
+impl Direction {
+    pub fn reverse(&self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+}
+
+// Funge space. The program's own rectangle is stored cell by cell, and
+// anything written outside it is kept by location, so that a `p` far away
+// costs one cell rather than everything in between.
 #[derive(Debug)]
-pub struct Space<T: num_traits::PrimInt> {
+pub struct Space<T: Cell> {
     points: Vec<Vec<T>>,
+    beyond: HashMap<(i64, i64), T>, // cells outside `points` that aren't spaces
+    bounds: ((i64, i64), (i64, i64)), // least and greatest points holding both
+    populated: usize,               // cells that aren't spaces
+    max_cells: Option<usize>,
+    overflow: Overflow, // for bytes that don't fit in a cell
+    generation: u64,
 }
 
 // Space trait implementations
-impl<T: num_traits::PrimInt> Space<T> {
+impl<T: Cell> Space<T> {
+    // the size of the program's rectangle, which never grows however far
+    // away a cell is put
     pub fn dims(&self) -> (usize, usize) {
         (self.points[0].len(), self.points.len())
     }
 
+    // the least and greatest points of the rectangle holding the program
+    // and everything written since, which the IP wraps around
+    pub fn bounds(&self) -> (Location, Location) {
+        let ((x0, y0), (x1, y1)) = self.bounds;
+        (Location(x0, y0), Location(x1, y1))
+    }
+
+    // counts the writes to the space, so that two moments with the same
+    // generation are known to have the same contents
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // anywhere that was never written reads as a space
     pub fn get(&self, at: &Location) -> T {
-        let mut loc = Location(at.0, at.1);
-        let (w, h) = self.dims();
-        loc.constrain(w, h);
-        self.points[loc.1 as usize][loc.0 as usize]
+        match self.point(at) {
+            Some(value) => value.clone(),
+            None => self
+                .beyond
+                .get(&(at.0, at.1))
+                .cloned()
+                .unwrap_or_else(|| T::from_i64(b' ' as i64)),
+        }
     }
 
-    fn set(&mut self, value: T, at: Location) -> () {
-        self.points[at.1 as usize][at.0 as usize] = value;
-        ()
+    // the stored cell for a location inside the program's rectangle
+    fn point(&self, at: &Location) -> Option<&T> {
+        let (x, y) = (usize::try_from(at.0).ok()?, usize::try_from(at.1).ok()?);
+        self.points.get(y)?.get(x)
+    }
+
+    // Writing outside the bounds grows them to fit, in any direction. Only
+    // cells that aren't spaces count towards the limit, however far apart
+    // they are.
+    pub(crate) fn set(&mut self, value: T, at: Location) -> Result<(), VmError> {
+        let blank = T::from_i64(b' ' as i64);
+        match (self.get(&at) == blank, value == blank) {
+            (true, false) => {
+                if self.max_cells.is_some_and(|max| self.populated >= max) {
+                    return Err(VmError::SpaceFull(self.max_cells.unwrap()));
+                }
+                self.populated += 1;
+            }
+            (false, true) => self.populated -= 1,
+            _ => (),
+        }
+
+        if value != blank {
+            let ((x0, y0), (x1, y1)) = self.bounds;
+            self.bounds = ((x0.min(at.0), y0.min(at.1)), (x1.max(at.0), y1.max(at.1)));
+        }
+        let point = match (usize::try_from(at.0), usize::try_from(at.1)) {
+            (Ok(x), Ok(y)) => self.points.get_mut(y).and_then(|row| row.get_mut(x)),
+            _ => None,
+        };
+        match point {
+            Some(point) => *point = value,
+            None if value == blank => {
+                self.beyond.remove(&(at.0, at.1));
+            }
+            None => {
+                self.beyond.insert((at.0, at.1), value);
+            }
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    // loads a file's contents with its top left corner at `at`, returning the
+    // size of the bounding rectangle. Spaces are transparent and never
+    // overwrite what is already there. In binary mode line breaks are stored
+    // as ordinary cells rather than starting a new row.
+    fn load(
+        &mut self,
+        data: &[u8],
+        at: &Location,
+        binary: bool,
+        mut written: impl FnMut(&Location, &T),
+    ) -> Result<(usize, usize), VmError> {
+        let lines: Vec<&[u8]> = match binary {
+            true => vec![data],
+            false => split_lines(data),
+        };
+
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        for (y, line) in lines.iter().enumerate() {
+            for (x, byte) in line.iter().enumerate() {
+                if *byte == b' ' {
+                    continue;
+                }
+                let value =
+                    T::fit(*byte as i64, self.overflow).ok_or(VmError::Overflow(T::SIZE * 8))?;
+                let to = Location(at.0 + x as i64, at.1 + y as i64);
+                written(&to, &value);
+                self.set(value, to)?;
+            }
+        }
+
+        Ok((width, lines.len()))
     }
 
-    fn new(code: String) -> Space<T> {
-        let mut max_len: usize = 0;
-        let mut max_height: usize = 0;
+    // renders the w x h region starting at `at` as lines of bytes. In text
+    // mode trailing spaces and trailing blank lines are dropped.
+    fn dump(&self, at: &Location, size: &Location, text: bool) -> Vec<u8> {
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        for y in 0..size.1.max(0) {
+            let mut line: Vec<u8> = (0..size.0.max(0))
+                .map(|x| {
+                    let value = self.get(&Location(at.0 + x, at.1 + y));
+                    byte(&value).unwrap_or(b' ')
+                })
+                .collect();
+            if text {
+                while line.last() == Some(&b' ') {
+                    line.pop();
+                }
+            }
+            lines.push(line);
+        }
 
-        // establish size
-        for line in code.lines() {
-            if max_len < line.len() {
-                max_len = line.len();
+        if text {
+            while lines.last().is_some_and(|line| line.is_empty()) {
+                lines.pop();
             }
-            max_height += 1;
         }
 
-        // initialise the whitespace filled funge space
-        let mut points: Vec<Vec<T>> = vec![vec![T::from(' ' as u8).unwrap(); max_len]; max_height];
+        let mut out = Vec::new();
+        for line in lines {
+            out.extend(line);
+            out.push(b'\n');
+        }
+        out
+    }
+
+    // Lays out a source file one byte per cell. Lines may end in LF, CR LF
+    // or CR, form feeds are dropped and everything else, tabs and non-ASCII
+    // bytes included, is stored as is. The space is at least 1 x 1 so that
+    // an empty program still has somewhere for the IP to be.
+    pub fn new(code: &[u8]) -> Space<T> {
+        let code: Vec<u8> = code.iter().copied().filter(|b| *b != b'\x0c').collect();
+        Space::from_rows(&split_lines(&code))
+    }
 
-        let mut y: usize = 0;
+    // Lays out rows that have already been split into lines, padding them
+    // with spaces to a rectangle
+    pub fn from_rows(lines: &[&[u8]]) -> Space<T> {
+        let width = lines
+            .iter()
+            .map(|line| line.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let height = lines.len().max(1);
 
-        // write the code to the funge space
-        for line in code.lines() {
-            let mut x: usize = 0;
-            for chr in line.chars() {
-                points[y][x] = match T::from(chr as usize) {
-                    Some(t) => t,
-                    None => T::from(' ' as u8).unwrap(),
-                };
-                x += 1;
+        let blank = T::from_i64(b' ' as i64);
+        let mut points: Vec<Vec<T>> = vec![vec![blank; width]; height];
+        for (y, line) in lines.iter().enumerate() {
+            for (x, byte) in line.iter().enumerate() {
+                points[y][x] = T::from_i64(*byte as i64);
             }
-            y += 1;
         }
 
-        Space { points }
+        let populated = lines
+            .iter()
+            .flat_map(|line| line.iter())
+            .filter(|b| **b != b' ')
+            .count();
+        Space {
+            points,
+            beyond: HashMap::new(),
+            bounds: ((0, 0), (width as i64 - 1, height as i64 - 1)),
+            populated,
+            max_cells: None,
+            overflow: Overflow::Wrap,
+            generation: 0,
+        }
+    }
+
+    // the same space holding another kind of cell, or None if a cell
+    // doesn't fit and the policy is to trap
+    fn convert<U: Cell>(&self, overflow: Overflow) -> Option<Space<U>> {
+        let points = self
+            .points
+            .iter()
+            .map(|row| row.iter().map(|v| U::fit(v.as_i64(), overflow)).collect())
+            .collect::<Option<_>>()?;
+        let beyond = self
+            .beyond
+            .iter()
+            .map(|(at, v)| Some((*at, U::fit(v.as_i64(), overflow)?)))
+            .collect::<Option<_>>()?;
+        Some(Space {
+            points,
+            beyond,
+            bounds: self.bounds,
+            populated: self.populated,
+            max_cells: self.max_cells,
+            overflow,
+            generation: self.generation,
+        })
+    }
+}
+
+// splits on any of the line terminators LF, CR LF or a lone CR
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'\n' => {
+                lines.push(&data[start..i]);
+                start = i + 1;
+            }
+            b'\r' => {
+                lines.push(&data[start..i]);
+                if data.get(i + 1) == Some(&b'\n') {
+                    i += 1;
+                }
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
     }
+    if start < data.len() {
+        lines.push(&data[start..]);
+    }
+    lines
 }
 
 #[derive(Debug)]
 pub struct Location(pub i64, pub i64);
 
-trait Movable {
+pub(crate) trait Movable {
     fn go(&mut self, direction: &Direction);
 }
 
@@ -299,95 +1186,958 @@ impl Movable for Location {
             Direction::West => Location(-1, 0),
         };
 
-        self.0 += delta.0;
-        self.1 += delta.1;
+        // wraps at the very edge, where the bounds wrap it back anyway
+        self.0 = self.0.wrapping_add(delta.0);
+        self.1 = self.1.wrapping_add(delta.1);
     }
 }
 
 impl Location {
-    fn constrain(&mut self, w: usize, h: usize) {
-        self.0 = (self.0 + w as i64) % w as i64;
-        self.1 = (self.1 + h as i64) % h as i64;
+    pub(crate) fn constrain(&mut self, w: usize, h: usize) {
+        self.0 = self.0.rem_euclid(w as i64);
+        self.1 = self.1.rem_euclid(h as i64);
+    }
+
+    // wraps into the rectangle from `least` to `greatest`, which may span
+    // more than an i64 can count
+    pub(crate) fn wrap(&mut self, least: &Location, greatest: &Location) {
+        let wrap = |at: i64, from: i64, to: i64| {
+            let span = to as i128 - from as i128 + 1;
+            (from as i128 + (at as i128 - from as i128).rem_euclid(span)) as i64
+        };
+        self.0 = wrap(self.0, least.0, greatest.0);
+        self.1 = wrap(self.1, least.1, greatest.1);
     }
 }
 
-#[derive(Debug)]
-pub struct Vm {
-    pub space: Space<usize>,
-    pub stack: Stack<usize>,
-    pub location: Location,
-    delta: Direction,
-    string_mode: bool,
-    stopped: bool,
-    rng: ThreadRng,
+// Controls whether and how `=` may run commands. The first word of the
+// command must be on the allowlist, the rest are passed as arguments
+// without going through a shell.
+#[derive(Debug, Clone)]
+pub struct ExecPolicy {
+    pub allowed: Vec<String>,
+    pub working_dir: Option<PathBuf>,
+    pub timeout: Option<Duration>,
 }
 
-#[allow(dead_code)]
-impl Vm {
-    pub const FOREVER: usize = 0;
+impl ExecPolicy {
+    // exit status pushed when a command is killed for running too long
+    pub const TIMED_OUT: usize = 124;
 
-    pub fn new(code: String) -> Vm {
-        Vm {
-            space: Space::new(code),
-            stack: Stack::new(),
-            location: Location(0, 0),
-            delta: Direction::West,
-            string_mode: false,
-            stopped: false,
-            rng: rand::thread_rng(),
+    pub fn new(allowed: Vec<String>) -> ExecPolicy {
+        ExecPolicy {
+            allowed,
+            working_dir: None,
+            timeout: None,
         }
     }
 
-    pub fn get_stack(&self) -> Stack<usize> {
-        Stack(self.stack.0.to_vec())
-    }
-
-    pub fn next_location(&mut self) -> () {
-        self.location.go(&self.delta);
-        let (w, h) = self.space.dims();
-        self.location.constrain(w, h);
-        ()
-    }
-
-    pub fn tick(&mut self) -> bool {
-        let instruction =
-            code::Instruction::from_raw(self.space.get(&self.location), &self.string_mode);
-
-        self.consume(instruction);
+    // `deadline` is the Vm's own time limit, if it has one, which the
+    // command is killed at as though it had run out its timeout
+    fn run(&self, command: &str, deadline: Option<Instant>) -> io::Result<usize> {
+        let mut words = command.split_whitespace();
+        let program = match words.next() {
+            Some(p) if self.allowed.iter().any(|a| a == p) => p,
+            _ => return Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+        };
 
-        if !self.stopped {
-            self.next_location();
+        let mut cmd = Command::new(program);
+        cmd.args(words).stdin(Stdio::null());
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
         }
+        let mut child = cmd.spawn()?;
 
-        return self.stopped;
-    }
+        let deadline = match (self.timeout.map(|t| Instant::now() + t), deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.code().unwrap_or(128) as usize);
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                child.kill()?;
+                child.wait()?;
+                return Ok(ExecPolicy::TIMED_OUT);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VmError {
+    StackOverflow(usize), // the stack grew deeper than the limit
+    SpaceFull(usize),     // funge space would have grown past this many cells
+    TooMuchOutput(usize), // the program tried to write more than this many bytes
+    TimedOut(Duration),   // the program ran for longer than this
+    Looping(Cycle),       // the program can never get out of this cycle
+    Overflow(usize),      // a value didn't fit in a cell of this many bits
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackOverflow(max) => write!(f, "stack exceeded {} cells", max),
+            VmError::SpaceFull(max) => write!(f, "funge space exceeded {} cells", max),
+            VmError::TooMuchOutput(max) => write!(f, "output exceeded {} bytes", max),
+            VmError::TimedOut(max) => write!(f, "ran for longer than {:?}", max),
+            VmError::Looping(cycle) => write!(
+                f,
+                "stuck in a loop of {} ticks through {} cells",
+                cycle.period,
+                cycle.cells.len()
+            ),
+            VmError::Overflow(bits) => write!(f, "a value overflowed a {}-bit cell", bits),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// Caps on the resources a program may use, for running untrusted code.
+// `None` means unlimited. By default only funge space is capped, at
+// DEFAULT_MAX_CELLS.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_stack: Option<usize>,
+    pub max_cells: Option<usize>, // cells in funge space that aren't spaces
+    pub max_output: Option<usize>,
+    // Checked before each step and while `=` waits for its command. A read
+    // from stdin that blocks is not interrupted.
+    pub max_time: Option<Duration>,
+}
+
+impl Limits {
+    pub const DEFAULT_MAX_CELLS: usize = 1 << 22;
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_stack: None,
+            max_cells: Some(Limits::DEFAULT_MAX_CELLS),
+            max_output: None,
+            max_time: None,
+        }
+    }
+}
+
+// The result of a single step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Running,
+    Stopped,
+}
+
+// How a run ended, with the number of ticks executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Stopped { ticks: usize },           // the program stopped itself with `@`
+    Quit { status: i32, ticks: usize }, // the program quit with `q`
+    OutOfTicks { ticks: usize },        // the tick limit was reached first
+}
+
+impl Outcome {
+    pub fn ticks(&self) -> usize {
+        match self {
+            Outcome::Stopped { ticks }
+            | Outcome::Quit { ticks, .. }
+            | Outcome::OutOfTicks { ticks } => *ticks,
+        }
+    }
+}
+
+// A cycle the VM can't leave: every `period` ticks it is back in exactly
+// the same state, having run the instructions at `cells` in between
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    pub period: usize,
+    pub cells: Vec<(i64, i64)>, // in the order they first ran, without repeats
+}
+
+// everything that decides what the VM does from here on
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct State<C> {
+    location: (i64, i64),
+    delta: Direction,
+    string_mode: bool,
+    stack: Vec<C>,
+    generation: u64,
+    events: u64,
+}
+
+// Spots exact cycles with Brent's algorithm. A state saved at tick 1, 2,
+// 4, 8 and so on is compared with every state up to the next save, so a
+// cycle is found within a couple of laps of it starting.
+#[derive(Debug)]
+struct LoopDetector<C> {
+    saved: Option<(u64, State<C>)>,
+    power: usize,
+    lambda: usize,          // ticks since the save
+    trail: Vec<(i64, i64)>, // where the IP has been since the save
+}
+
+impl<C: Cell> LoopDetector<C> {
+    fn new() -> LoopDetector<C> {
+        LoopDetector {
+            saved: None,
+            power: 1,
+            lambda: 0,
+            trail: Vec::new(),
+        }
+    }
+
+    fn observe(&mut self, state: State<C>) -> Option<Cycle> {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        self.lambda += 1;
+        if let Some((saved_hash, saved)) = &self.saved {
+            if *saved_hash == hash && *saved == state {
+                let mut seen = HashSet::new();
+                return Some(Cycle {
+                    period: self.lambda,
+                    cells: self
+                        .trail
+                        .iter()
+                        .copied()
+                        .filter(|c| seen.insert(*c))
+                        .collect(),
+                });
+            }
+        }
+
+        if self.lambda == self.power {
+            self.power *= 2;
+            self.lambda = 0;
+            self.trail = vec![state.location];
+            self.saved = Some((hash, state));
+        } else {
+            self.trail.push(state.location);
+        }
+        None
+    }
+}
+
+// the program's input and output streams
+struct Io {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl fmt::Debug for Io {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Io")
+    }
+}
+
+// An output handle that can still be read after a clone of it has been
+// handed to a Vm, for capturing what a program prints
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    // empties the buffer, returning what was in it
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Configures and creates a Vm. Anything not set falls back to a default:
+// an empty program, Funge-98, stdin and stdout, an unseeded RNG, the
+// default limits, which cap only funge space, every built-in fingerprint
+// and no `=`.
+pub struct VmBuilder {
+    source: Vec<u8>,
+    space: Option<Space<usize>>, // laid out ahead of time, used instead of source
+    dialect: Dialect,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seed: Option<u64>,
+    limits: Limits,
+    fingerprints: Vec<usize>,
+    base_dir: PathBuf,
+    exec: Option<ExecPolicy>,
+    detect_loops: bool,
+    coverage: bool,
+    overflow: Overflow,
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        VmBuilder::new()
+    }
+}
+
+impl VmBuilder {
+    pub fn new() -> VmBuilder {
+        VmBuilder {
+            source: Vec::new(),
+            space: None,
+            dialect: Dialect::default(),
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            seed: None,
+            limits: Limits::default(),
+            fingerprints: fingerprint::BUILT_IN.iter().map(|f| f.id()).collect(),
+            base_dir: PathBuf::from("."),
+            exec: None,
+            detect_loops: false,
+            coverage: false,
+            overflow: Overflow::Wrap,
+        }
+    }
+
+    pub fn source(mut self, code: impl AsRef<[u8]>) -> VmBuilder {
+        self.source = code.as_ref().to_vec();
+        self
+    }
+
+    // a program already laid out in funge space, as `befunge!` produces
+    pub fn space(mut self, space: Space<usize>) -> VmBuilder {
+        self.space = Some(space);
+        self
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> VmBuilder {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn input(mut self, input: impl BufRead + 'static) -> VmBuilder {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn output(mut self, output: impl Write + 'static) -> VmBuilder {
+        self.output = Box::new(output);
+        self
+    }
+
+    // makes `?` deterministic
+    pub fn seed(mut self, seed: u64) -> VmBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> VmBuilder {
+        self.limits = limits;
+        self
+    }
+
+    // restricts `(` to the named built-in fingerprints, unknown names are ignored
+    pub fn fingerprints(mut self, names: &[&str]) -> VmBuilder {
+        self.fingerprints = fingerprint::BUILT_IN
+            .iter()
+            .filter(|f| names.contains(&f.name))
+            .map(|f| f.id())
+            .collect();
+        self
+    }
+
+    // file names used by `i` and `o` are resolved relative to this directory
+    pub fn base_dir(mut self, dir: PathBuf) -> VmBuilder {
+        self.base_dir = dir;
+        self
+    }
+
+    // `=` reflects unless a policy is set
+    pub fn exec_policy(mut self, policy: ExecPolicy) -> VmBuilder {
+        self.exec = Some(policy);
+        self
+    }
+
+    // stop with VmError::Looping as soon as the program is caught in a cycle
+    // it can never leave, rather than running until the tick limit
+    pub fn detect_loops(mut self, detect: bool) -> VmBuilder {
+        self.detect_loops = detect;
+        self
+    }
+
+    // record which cells run and which way branches go, see Vm::coverage
+    pub fn coverage(mut self, record: bool) -> VmBuilder {
+        self.coverage = record;
+        self
+    }
+
+    // what arithmetic does when a result doesn't fit in a cell, and what
+    // happens to bytes loaded into cells too narrow for them
+    pub fn overflow(mut self, overflow: Overflow) -> VmBuilder {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn build(self) -> Vm {
+        self.build_with_cells()
+    }
+
+    // a Vm whose stack and funge space hold `C`, such as a BigInt or a
+    // narrower integer than the usual usize
+    pub fn build_with_cells<C: Cell>(self) -> Vm<C> {
+        self.build_observed(())
+    }
+
+    // a Vm that tells `observer` what it does as it runs
+    pub fn build_observed<C: Cell, O: Observer<C>>(self, observer: O) -> Vm<C, O> {
+        let laid_out = match &self.space {
+            Some(space) => space,
+            None => &Space::new(&self.source),
+        };
+        // a program that doesn't fit in its cells traps on the first step
+        let (mut space, overflowed) = match laid_out.convert(self.overflow) {
+            Some(space) => (space, false),
+            None => (laid_out.convert(Overflow::Wrap).unwrap(), true),
+        };
+        space.overflow = self.overflow;
+        space.max_cells = self.limits.max_cells;
+
+        let coverage = match self.coverage {
+            true => Some(Coverage::new(&space)),
+            false => None,
+        };
+
+        Vm {
+            space,
+            stack: Stack::new(),
+            location: Location(0, 0),
+            delta: Direction::East,
+            string_mode: false,
+            stopped: false,
+            exit_status: None,
+            rng: match self.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            dialect: self.dialect,
+            io: Io {
+                input: self.input,
+                output: self.output,
+            },
+            fingerprints: self.fingerprints,
+            semantics: vec![Vec::new(); 26],
+            base_dir: self.base_dir,
+            exec: self.exec,
+            limits: self.limits,
+            started: None,
+            written: 0,
+            events: 0,
+            detector: match self.detect_loops {
+                true => Some(LoopDetector::new()),
+                false => None,
+            },
+            coverage,
+            overflow: self.overflow,
+            overflowed,
+            observer,
+            registry: Registry::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Vm<C: Cell = usize, O: Observer<C> = ()> {
+    pub space: Space<C>,
+    pub stack: Stack<C>,
+    pub location: Location,
+    delta: Direction,
+    string_mode: bool,
+    stopped: bool,
+    exit_status: Option<i32>,
+    rng: StdRng,
+    dialect: Dialect,
+    io: Io,
+    fingerprints: Vec<usize>,
+    semantics: Vec<Vec<fingerprint::Semantic>>,
+    base_dir: PathBuf,
+    exec: Option<ExecPolicy>,
+    limits: Limits,
+    started: Option<Instant>,
+    written: usize,
+    events: u64, // bumped by anything whose effect the loop detector can't see
+    detector: Option<LoopDetector<C>>,
+    coverage: Option<Coverage>,
+    overflow: Overflow,
+    overflowed: bool, // the program didn't fit in its cells
+    observer: O,
+    registry: Registry<C>,
+}
+
+#[allow(dead_code)]
+impl Vm {
+    pub const FOREVER: usize = 0;
+
+    // a Vm running `code` with the defaults, see VmBuilder for the rest
+    pub fn new(code: impl AsRef<[u8]>) -> Vm {
+        VmBuilder::new().source(code).build()
+    }
+}
+
+#[allow(dead_code)]
+impl<C: Cell, O: Observer<C>> Vm<C, O> {
+    pub fn get_stack(&self) -> Stack<C> {
+        Stack(self.stack.0.to_vec())
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    // the custom instructions, consulted before the built-in ones
+    pub fn registry_mut(&mut self) -> &mut Registry<C> {
+        &mut self.registry
+    }
+
+    // the stack, as instructions push and pop it
+    fn stack_mut(&mut self) -> ObservedStack<'_, C, O> {
+        ObservedStack {
+            stack: &mut self.stack,
+            observer: &mut self.observer,
+        }
+    }
+
+    pub fn next_location(&mut self) -> () {
+        self.location.go(&self.delta);
+        let (least, greatest) = self.space.bounds();
+        self.location.wrap(&least, &greatest);
+        self.observer.moved(&self.location, self.delta);
+        ()
+    }
+
+    pub fn tick(&mut self) -> Result<bool, VmError> {
+        Ok(self.step()? == Step::Stopped)
+    }
+
+    // executes the instruction under the IP and moves on
+    pub fn step(&mut self) -> Result<Step, VmError> {
+        if self.stopped {
+            return Ok(Step::Stopped);
+        }
+        if self.overflowed {
+            return Err(VmError::Overflow(C::SIZE * 8));
+        }
+
+        if let Some(max) = self.limits.max_time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if started.elapsed() > max {
+                return Err(VmError::TimedOut(max));
+            }
+        }
+
+        self.observer.tick(&self.location);
+        let registry = &self.registry;
+        let instruction = code::Instruction::from_raw_with(
+            self.space.get(&self.location).as_i64() as usize,
+            &self.string_mode,
+            &self.dialect,
+            |c| registry.contains(c),
+        );
+        self.observer.decode(&self.location, &instruction);
+
+        if matches!(
+            instruction,
+            code::Instruction::MoveRandom
+                | code::Instruction::ReadInt
+                | code::Instruction::ReadChr
+                | code::Instruction::InputFile
+                | code::Instruction::Execute
+                | code::Instruction::LoadSemantics
+                | code::Instruction::UnloadSemantics
+                | code::Instruction::Custom(_)
+        ) {
+            self.events += 1;
+        }
+
+        let (x, y) = (self.location.0 as usize, self.location.1 as usize);
+        let branch = matches!(
+            instruction,
+            code::Instruction::MoveEastOrWest | code::Instruction::MoveNorthOrSouth
+        );
+
+        self.consume(instruction)?;
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(x, y);
+            if branch {
+                coverage.branch(x, y, &self.delta);
+            }
+        }
+
+        if let Some(max) = self.limits.max_stack {
+            if self.stack.0.len() > max {
+                return Err(VmError::StackOverflow(max));
+            }
+        }
+
+        if self.stopped {
+            self.io.output.flush().ok();
+            return Ok(Step::Stopped);
+        }
+
+        self.next_location();
+
+        if self.detector.is_some() {
+            let state = State {
+                location: (self.location.0, self.location.1),
+                delta: self.delta,
+                string_mode: self.string_mode,
+                stack: self.stack.0.clone(),
+                generation: self.space.generation(),
+                events: self.events,
+            };
+            if let Some(cycle) = self.detector.as_mut().and_then(|d| d.observe(state)) {
+                return Err(VmError::Looping(cycle));
+            }
+        }
+        Ok(Step::Running)
+    }
+
+    // the status popped by `q`, if the program quit that way
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    // what has run so far, if the Vm was built to record it
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
 
     pub fn get_location(&self) -> Location {
         Location(self.location.0, self.location.1)
     }
 
-    pub fn run_for(&mut self, tick_limit: usize) -> Result<usize, ()> {
+    pub fn get_delta(&self) -> Direction {
+        self.delta
+    }
+
+    pub fn get_string_mode(&self) -> bool {
+        self.string_mode
+    }
+
+    pub fn run_for(&mut self, tick_limit: usize) -> Result<usize, VmError> {
+        self.run(tick_limit).map(|outcome| outcome.ticks())
+    }
+
+    // steps until the program stops or `tick_limit` ticks have run
+    pub fn run(&mut self, tick_limit: usize) -> Result<Outcome, VmError> {
         let mut ticks: usize = 0;
-        while !self.tick() {
+        while tick_limit == Vm::FOREVER || ticks < tick_limit {
+            let step = self.step();
+            if step.is_err() {
+                self.io.output.flush().ok();
+            }
             ticks += 1;
-            if !(ticks < tick_limit || tick_limit == Vm::FOREVER) {
-                break;
+            if step? == Step::Stopped {
+                return Ok(match self.exit_status {
+                    Some(status) => Outcome::Quit { status, ticks },
+                    None => Outcome::Stopped { ticks },
+                });
+            }
+        }
+
+        self.io.output.flush().ok();
+        Ok(Outcome::OutOfTicks { ticks })
+    }
+
+    fn reflect(&mut self) {
+        self.delta = self.delta.reverse();
+    }
+
+    fn input_file(&mut self) -> Result<(), VmError> {
+        let name = self.stack_mut().pop_string();
+        let path = self.base_dir.join(name);
+        let flags = self.stack_mut().pop();
+        let at = self.stack_mut().pop_vector();
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(_) => {
+                self.reflect();
+                return Ok(());
+            }
+        };
+        let observer = &mut self.observer;
+        let (w, h) = self
+            .space
+            .load(&data, &at, flags.as_i64() & 1 == 1, |to, value| {
+                observer.write(to, value)
+            })?;
+        self.stack_mut().push_vector(&Location(w as i64, h as i64));
+        self.stack_mut().push_vector(&at);
+        Ok(())
+    }
+
+    fn output_file(&mut self) -> io::Result<()> {
+        let name = self.stack_mut().pop_string();
+        let path = self.base_dir.join(name);
+        let flags = self.stack_mut().pop();
+        let at = self.stack_mut().pop_vector();
+        let size = self.stack_mut().pop_vector();
+
+        fs::write(path, self.space.dump(&at, &size, flags.as_i64() & 1 == 1))
+    }
+
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        self.written += bytes.len();
+        if let Some(max) = self.limits.max_output {
+            if self.written > max {
+                return Err(VmError::TooMuchOutput(max));
+            }
+        }
+        match self.io.output.write_all(bytes) {
+            Ok(()) => self.observer.output(bytes),
+            Err(_) => self.reflect(),
+        }
+        Ok(())
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        match self.io.input.fill_buf() {
+            Ok(buf) => buf.first().copied(),
+            Err(_) => None,
+        }
+    }
+
+    // `~` pushes the next byte of input, reflecting at end of input
+    fn read_chr(&mut self) -> Result<(), VmError> {
+        match self.peek_byte() {
+            Some(b) => {
+                self.io.input.consume(1);
+                let value =
+                    C::fit(b as i64, self.overflow).ok_or(VmError::Overflow(C::SIZE * 8))?;
+                self.stack_mut().push(value);
+            }
+            None => self.reflect(),
+        }
+        Ok(())
+    }
+
+    // `&` skips anything up to the next decimal number and pushes it,
+    // reflecting at end of input. Fixed width cells take the digits as they
+    // come, so a long run of them needs no more room than the cell, and
+    // saturate if there are too many unless the policy is to trap.
+    fn read_int(&mut self) -> Result<(), VmError> {
+        loop {
+            match self.peek_byte() {
+                Some(b) if b.is_ascii_digit() => break,
+                Some(_) => self.io.input.consume(1),
+                None => {
+                    self.reflect();
+                    return Ok(());
+                }
+            }
+        }
+
+        let overflow = match self.overflow {
+            Overflow::Trap => Overflow::Trap,
+            _ => Overflow::Saturate,
+        };
+
+        let mut digits = Vec::new(); // for cells with no fixed width
+        let mut value = C::from_i64(0);
+        while let Some(b) = self.peek_byte().filter(|b| b.is_ascii_digit()) {
+            match C::SIZE {
+                0 => digits.push(b),
+                _ => {
+                    value =
+                        append_digit(&value, b, overflow).ok_or(VmError::Overflow(C::SIZE * 8))?
+                }
             }
+            self.io.input.consume(1);
         }
+        if C::SIZE == 0 {
+            value = C::from_decimal(&digits);
+        }
+        self.stack_mut().push(value);
+        Ok(())
+    }
 
-        match ticks {
-            0 => Result::Err(()),
-            x => Result::Ok(x),
+    // the fingerprint id is popped as a count followed by that many cells
+    // None, leaving the id where it was, when the count is negative or more
+    // than the stack holds or an id can have
+    fn pop_fingerprint(&mut self) -> Option<&'static fingerprint::Fingerprint> {
+        let count = signed(self.stack_mut().pop());
+        let most = self.stack.as_slice().len().min(fingerprint::MAX_ID_CELLS);
+        if count < 0 || count as usize > most {
+            return None;
+        }
+        let mut id: usize = 0;
+        for _ in 0..count {
+            id = id
+                .wrapping_mul(256)
+                .wrapping_add(signed(self.stack_mut().pop()) as usize);
+        }
+        match self.fingerprints.contains(&id) {
+            true => fingerprint::find(id),
+            false => None,
         }
     }
 
-    pub fn consume(&mut self, instruction: code::Instruction) -> () {
+    fn load_semantics(&mut self) {
+        match self.pop_fingerprint() {
+            Some(fp) => {
+                for (letter, semantic) in fp.semantics {
+                    self.semantics[(*letter as u8 - b'A') as usize].push(*semantic);
+                }
+                self.stack_mut().push(cell(fp.id() as i64));
+                self.stack_mut().push(cell(1));
+            }
+            None => self.reflect(),
+        }
+    }
+
+    fn unload_semantics(&mut self) {
+        match self.pop_fingerprint() {
+            Some(fp) => {
+                for (letter, _) in fp.semantics {
+                    self.semantics[(*letter as u8 - b'A') as usize].pop();
+                }
+            }
+            None => self.reflect(),
+        }
+    }
+
+    fn semantic(&mut self, letter: char) {
+        let bound = self.semantics[(letter as u8 - b'A') as usize].last();
+        match bound.copied().unwrap_or(fingerprint::Semantic::Reflect) {
+            fingerprint::Semantic::Reflect => self.reflect(),
+            fingerprint::Semantic::Push(value) => self.stack_mut().push(cell(value)),
+            fingerprint::Semantic::And => self.bitwise(C::and),
+            fingerprint::Semantic::Or => self.bitwise(C::or),
+            fingerprint::Semantic::Xor => self.bitwise(C::xor),
+            fingerprint::Semantic::Not => {
+                let a = self.stack_mut().pop();
+                self.stack_mut().push(a.not());
+            }
+        }
+    }
+
+    fn bitwise(&mut self, op: fn(&C, &C) -> C) {
+        let (a, b) = (self.stack_mut().pop(), self.stack_mut().pop());
+        self.stack_mut().push(op(&b, &a));
+    }
+
+    fn execute(&mut self) -> io::Result<()> {
+        let command = self.stack_mut().pop_string();
+        let policy = match &self.exec {
+            Some(policy) => policy,
+            None => return Err(io::Error::from(io::ErrorKind::Unsupported)),
+        };
+
+        // keep our own output ahead of anything the command prints
+        self.io.output.flush()?;
+        let deadline = self.started.zip(self.limits.max_time).map(|(s, t)| s + t);
+        let status = policy.run(&command, deadline)?;
+        self.stack_mut().push(cell(status as i64));
+        Ok(())
+    }
+
+    fn delta_vector(&self) -> Location {
+        let mut delta = Location(0, 0);
+        delta.go(&self.delta);
+        delta
+    }
+
+    // Fails if the policy is to trap and an item it pushes doesn't fit in a
+    // cell, as the handprint doesn't in one of 8 bits
+    fn sys_info(&mut self) -> Result<(), VmError> {
+        let n = signed(self.stack_mut().pop());
+        let depth = self.stack.0.len();
+        let (least, greatest) = self.space.bounds();
+        let (date, time) = date_and_time(now());
+        let delta = self.delta_vector();
+        let at = self.get_location();
+        let exec = self.exec.is_some() as i64;
+
+        // listed from the last item to the first, so that item 1 ends up on top
+        let items = [
+            0, // environment variables are not exposed
+            0, // nor are command line arguments
+            0,
+            depth as i64,
+            1, // number of stacks on the stack stack
+            time as i64,
+            date as i64,
+            greatest.0.saturating_sub(least.0), // greatest point, from the least
+            greatest.1.saturating_sub(least.1),
+            least.0, // least point
+            least.1,
+            0, // storage offset
+            0,
+            delta.0,
+            delta.1,
+            at.0,
+            at.1,
+            0, // team number
+            0, // IP id
+            2, // dimensions
+            std::path::MAIN_SEPARATOR as i64,
+            exec,       // paradigm, 1 when = may run commands
+            10,         // version
+            0x52534246, // handprint, "RSBF"
+            C::SIZE as i64,
+            0b00110 | exec << 3,
+        ];
+        let fit = |item: i64| C::fit(item, self.overflow).ok_or(VmError::Overflow(C::SIZE * 8));
+
+        // y with a positive argument acts like a pick into the list, which
+        // may reach past it into the rest of the stack
+        if n > 0 {
+            let nth = n as usize - 1;
+            let picked = match items.len().checked_sub(nth + 1) {
+                Some(i) => fit(items[i])?,
+                None => {
+                    let below = self.stack.0.iter().rev().nth(nth - items.len());
+                    below.cloned().unwrap_or_else(|| cell(0))
+                }
+            };
+            self.stack_mut().push(picked);
+        } else {
+            let info = items.into_iter().map(fit).collect::<Result<Vec<C>, _>>()?;
+            for value in info {
+                self.stack_mut().push(value);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn consume(&mut self, instruction: code::Instruction) -> Result<(), VmError> {
+        let overflow = self.overflow;
         match instruction {
             code::Instruction::NoOp => (),
             code::Instruction::Stop => {
                 self.stopped = true;
                 ()
             }
+            code::Instruction::Quit => {
+                self.exit_status = Some(signed(self.stack_mut().pop()) as i32);
+                self.stopped = true;
+            }
             code::Instruction::Skip => {
                 self.location.go(&self.delta);
                 ()
@@ -396,21 +2146,22 @@ impl Vm {
                 self.string_mode = !self.string_mode;
                 ()
             }
+            code::Instruction::Reflect => self.reflect(),
             code::Instruction::Move(dir) => {
                 self.delta = dir;
                 ()
             }
             code::Instruction::MoveEastOrWest => {
-                self.delta = match self.stack.pop() {
-                    0 => Direction::East,
-                    _ => Direction::West,
+                self.delta = match self.stack_mut().pop().is_zero() {
+                    true => Direction::East,
+                    false => Direction::West,
                 };
                 ()
             }
             code::Instruction::MoveNorthOrSouth => {
-                self.delta = match self.stack.pop() {
-                    0 => Direction::North,
-                    _ => Direction::South,
+                self.delta = match self.stack_mut().pop().is_zero() {
+                    true => Direction::South,
+                    false => Direction::North,
                 };
                 ()
             }
@@ -422,54 +2173,1059 @@ impl Vm {
                     _ => Direction::West,
                 }
             }
-            code::Instruction::Duplicate => self.stack.dupe(),
-            code::Instruction::Swap => self.stack.swap(),
-            code::Instruction::Pop => match self.stack.pop() {
+            code::Instruction::Duplicate => self.stack_mut().dupe(),
+            code::Instruction::Swap => self.stack_mut().swap(),
+            code::Instruction::Pop => match self.stack_mut().pop() {
                 _ => (),
             },
-            code::Instruction::Add => self.stack.apply(ops::NAry::<usize, 2>::add()),
-            code::Instruction::Sub => self.stack.apply(ops::NAry::<usize, 2>::sub()),
-            code::Instruction::Mul => self.stack.apply(ops::NAry::<usize, 2>::mul()),
-            code::Instruction::Div => self.stack.apply(ops::NAry::<usize, 2>::div()),
-            code::Instruction::Mod => self.stack.apply(ops::NAry::<usize, 2>::rem()),
-            code::Instruction::GreaterThan => self.stack.apply(ops::NAry::<usize, 2>::gt()),
-            code::Instruction::Not => self.stack.apply(ops::NAry::<usize, 1>::not()),
+            code::Instruction::Add => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::add().overflow(overflow))?,
+            code::Instruction::Sub => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::sub().overflow(overflow))?,
+            code::Instruction::Mul => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::mul().overflow(overflow))?,
+            code::Instruction::Div => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::div().overflow(overflow))?,
+            code::Instruction::Mod => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::rem().overflow(overflow))?,
+            code::Instruction::GreaterThan => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 2>::gt().overflow(overflow))?,
+            code::Instruction::Not => self
+                .stack_mut()
+                .apply(ops::NAry::<C, 1>::not().overflow(overflow))?,
             code::Instruction::PrintInt => {
-                print!("{}", self.stack.pop() as usize);
-                ()
+                let output = format!("{} ", self.stack_mut().pop().to_decimal());
+                self.write_output(output.as_bytes())?
             }
             code::Instruction::PrintChr => {
-                let mut output = String::from("");
-                output.push(self.stack.pop() as u8 as char);
-                print!("{}", output);
-                ()
+                let output = [signed(self.stack_mut().pop()) as u8];
+                self.write_output(&output)?
             }
             code::Instruction::Put => {
-                let (y, x, v) = (self.stack.pop(), self.stack.pop(), self.stack.pop());
-                self.space.set(v, Location(x as i64, y as i64))
+                let mut stack = self.stack_mut();
+                let (y, x, v) = (stack.pop(), stack.pop(), stack.pop());
+                let at = Location(signed(x), signed(y));
+                self.observer.write(&at, &v);
+                self.space.set(v, at)?
             }
             code::Instruction::Get => {
-                let (y, x) = (self.stack.pop(), self.stack.pop());
-                self.stack
-                    .push(self.space.get(&Location(x as i64, y as i64)))
-            }
-            code::Instruction::ReadInt => {
-                let mut input = io::stdin();
-
-                let mut buf = String::new();
-                input.read_to_string(&mut buf).expect("saw it coming");
-
-                // use the ordinal trick
-                let mut int = buf.pop().unwrap() as isize - '0' as isize;
-                int = match int {
-                    x if x > 9 => 9,
-                    x if x < 0 => 0,
-                    x => x,
-                };
-                self.stack.push(int as usize)
+                let mut stack = self.stack_mut();
+                let (y, x) = (stack.pop(), stack.pop());
+                let at = Location(signed(x), signed(y));
+                let value = self.space.get(&at);
+                self.observer.read(&at, &value);
+                self.stack_mut().push(value)
+            }
+            code::Instruction::ReadInt => self.read_int()?,
+            code::Instruction::ReadChr => self.read_chr()?,
+            code::Instruction::InputFile => self.input_file()?,
+            code::Instruction::OutputFile => {
+                if self.output_file().is_err() {
+                    self.reflect();
+                }
+            }
+            code::Instruction::Execute => {
+                if self.execute().is_err() {
+                    self.reflect();
+                }
+            }
+            code::Instruction::SysInfo => self.sys_info()?,
+            code::Instruction::LoadSemantics => self.load_semantics(),
+            code::Instruction::UnloadSemantics => self.unload_semantics(),
+            code::Instruction::Semantic(letter) => self.semantic(letter),
+            code::Instruction::Custom(c) => match self.registry.instructions.get(&c) {
+                Some(instruction) => instruction(&mut VmContext {
+                    stack: &mut self.stack,
+                    space: &mut self.space,
+                    location: &mut self.location,
+                    delta: &mut self.delta,
+                    observer: &mut self.observer,
+                })?,
+                None => self.reflect(),
+            },
+            code::Instruction::ReadAndPush(x) => self.stack_mut().push(cell(x as i64)),
+        }
+        Ok(())
+    }
+}
+
+// wasm32-unknown-unknown has no clock of its own, so ask the browser's
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64)
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+// encodes the UTC date and time the way `y` reports them:
+// ((year - 1900) * 256 * 256) + (month * 256) + day and
+// (hour * 256 * 256) + (minute * 256) + second
+fn date_and_time(now: SystemTime) -> (usize, usize) {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = ((year - 1900) * 256 * 256 + month * 256 + day) as usize;
+    let time = ((rem / 3600) * 256 * 256 + ((rem % 3600) / 60) * 256 + rem % 60) as usize;
+    (date, time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::code::Instruction;
+    use super::*;
+    use std::io::Cursor;
+
+    fn vm(code: &str, input: &str) -> (Vm, SharedBuffer) {
+        let output = SharedBuffer::new();
+        let vm = VmBuilder::new()
+            .source(code)
+            .input(Cursor::new(input.as_bytes().to_vec()))
+            .output(output.clone())
+            .seed(0)
+            .fingerprints(&["ROMA", "BOOL"])
+            .build();
+        (vm, output)
+    }
+
+    // the stack after running `instruction` on a stack holding `setup`
+    fn after(setup: &[usize], instruction: Instruction) -> Vec<usize> {
+        let (mut vm, _) = vm("@", "");
+        for value in setup {
+            vm.stack.push(*value);
+        }
+        vm.consume(instruction).unwrap();
+        vm.stack.as_slice().to_vec()
+    }
+
+    fn push_string(vm: &mut Vm, text: &str) {
+        vm.stack.push(0);
+        for b in text.bytes().rev() {
+            vm.stack.push(b as usize);
+        }
+    }
+
+    // pushes a fingerprint id the way `"AMOR"4(` would for ROMA
+    fn push_fingerprint(vm: &mut Vm, name: &str) {
+        for b in name.bytes().rev() {
+            vm.stack.push(b as usize);
+        }
+        vm.stack.push(name.len());
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rsbefunge-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn decode(c: char) -> Instruction {
+        Instruction::from_raw(c as usize, &false, &Dialect::Befunge98)
+    }
+
+    #[test]
+    fn read_and_push() {
+        assert_eq!(after(&[1], Instruction::ReadAndPush(7)), vec![1, 7]);
+        assert!(matches!(decode('7'), Instruction::ReadAndPush(7)));
+        assert!(matches!(decode('c'), Instruction::ReadAndPush(12)));
+    }
+
+    #[test]
+    fn no_op() {
+        let (mut vm, _) = vm("@", "");
+        vm.consume(Instruction::NoOp).unwrap();
+        assert!(vm.stack.as_slice().is_empty());
+        assert_eq!(vm.delta, Direction::East);
+    }
+
+    #[test]
+    fn programs_start_heading_east() {
+        let (mut vm, output) = vm("1.@.2", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"1 ");
+    }
+
+    #[test]
+    fn stop() {
+        let (mut vm, _) = vm("@", "");
+        assert_eq!(vm.run(10).unwrap(), Outcome::Stopped { ticks: 1 });
+        assert_eq!(vm.exit_status(), None);
+    }
+
+    #[test]
+    fn quit() {
+        let (mut vm, _) = vm("3q", "");
+        assert_eq!(
+            vm.run(10).unwrap(),
+            Outcome::Quit {
+                status: 3,
+                ticks: 2
+            }
+        );
+    }
+
+    #[test]
+    fn skip() {
+        let (mut vm, _) = vm("#1@", "");
+        vm.run(10).unwrap();
+        assert!(vm.stack.as_slice().is_empty());
+    }
+
+    #[test]
+    fn string_mode() {
+        let (mut vm, _) = vm("\"a 1\"@", "");
+        vm.run(10).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[97, 32, 49]);
+    }
+
+    #[test]
+    fn reflect() {
+        let (mut vm, _) = vm("@", "");
+        vm.consume(Instruction::Reflect).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+        assert!(matches!(decode('r'), Instruction::Reflect));
+    }
+
+    #[test]
+    fn unknown_instructions_reflect() {
+        assert!(matches!(decode('Z'), Instruction::Semantic('Z')));
+        assert!(matches!(decode('{'), Instruction::Reflect));
+        assert!(matches!(
+            Instruction::from_raw('{' as usize, &false, &Dialect::Befunge93),
+            Instruction::ReadAndPush(123)
+        ));
+    }
+
+    #[test]
+    fn move_in_a_direction() {
+        let (mut vm, _) = vm("@", "");
+        for (c, dir) in [
+            ('^', Direction::North),
+            ('v', Direction::South),
+            ('<', Direction::West),
+            ('>', Direction::East),
+        ] {
+            vm.consume(decode(c)).unwrap();
+            assert_eq!(vm.delta, dir);
+        }
+    }
+
+    #[test]
+    fn move_east_or_west() {
+        let (mut vm, _) = vm("@", "");
+        vm.stack.push(5);
+        vm.consume(Instruction::MoveEastOrWest).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+        vm.stack.push(0);
+        vm.consume(Instruction::MoveEastOrWest).unwrap();
+        assert_eq!(vm.delta, Direction::East);
+    }
+
+    #[test]
+    fn move_north_or_south() {
+        let (mut vm, _) = vm("@", "");
+        vm.stack.push(5);
+        vm.consume(Instruction::MoveNorthOrSouth).unwrap();
+        assert_eq!(vm.delta, Direction::North);
+        vm.stack.push(0);
+        vm.consume(Instruction::MoveNorthOrSouth).unwrap();
+        assert_eq!(vm.delta, Direction::South);
+    }
+
+    #[test]
+    fn vertical_if_goes_south_on_zero_and_north_otherwise() {
+        let (mut zero, output) = vm("0v\n |\n 2\n .\n @", "");
+        assert!(matches!(zero.run(100).unwrap(), Outcome::Stopped { .. }));
+        assert_eq!(output.take(), b"2 ");
+
+        let (mut other, output) = vm("5v@\n >|\n  .", "");
+        assert!(matches!(other.run(100).unwrap(), Outcome::Stopped { .. }));
+        assert_eq!(output.take(), b"");
+    }
+
+    #[test]
+    fn move_random_goes_every_way() {
+        let (mut vm, _) = vm("@", "");
+        let mut seen = Vec::new();
+        for _ in 0..100 {
+            vm.consume(Instruction::MoveRandom).unwrap();
+            if !seen.contains(&vm.delta) {
+                seen.push(vm.delta);
             }
-            code::Instruction::ReadChr => (),
-            code::Instruction::ReadAndPush(x) => self.stack.push(x),
         }
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[test]
+    fn duplicate() {
+        assert_eq!(after(&[1, 2], Instruction::Duplicate), vec![1, 2, 2]);
+        assert_eq!(after(&[], Instruction::Duplicate), vec![0, 0]);
+    }
+
+    #[test]
+    fn swap() {
+        assert_eq!(after(&[1, 2, 3], Instruction::Swap), vec![1, 3, 2]);
+        assert_eq!(after(&[4], Instruction::Swap), vec![4, 0]);
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two_values() {
+        let (mut vm, output) = vm("12\\..@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"1 2 ");
+    }
+
+    #[test]
+    fn pop() {
+        assert_eq!(after(&[1, 2], Instruction::Pop), vec![1]);
+        assert_eq!(after(&[], Instruction::Pop), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(after(&[2, 3], Instruction::Add), vec![5]);
+        assert_eq!(after(&[usize::MAX, 1], Instruction::Add), vec![0]);
+    }
+
+    #[test]
+    fn sub() {
+        assert_eq!(after(&[7, 3], Instruction::Sub), vec![4]);
+        assert_eq!(after(&[3, 5], Instruction::Sub), vec![cell::<usize>(-2)]);
+    }
+
+    #[test]
+    fn mul() {
+        assert_eq!(after(&[6, 7], Instruction::Mul), vec![42]);
+        assert_eq!(
+            after(&[cell::<usize>(-3), 4], Instruction::Mul),
+            vec![cell::<usize>(-12)]
+        );
+    }
+
+    #[test]
+    fn div() {
+        assert_eq!(after(&[9, 2], Instruction::Div), vec![4]);
+        assert_eq!(after(&[9, 0], Instruction::Div), vec![0]);
+        assert_eq!(
+            after(&[cell::<usize>(-7), 2], Instruction::Div),
+            vec![cell::<usize>(-3)]
+        );
+    }
+
+    #[test]
+    fn modulo() {
+        assert_eq!(after(&[9, 5], Instruction::Mod), vec![4]);
+        assert_eq!(after(&[9, 0], Instruction::Mod), vec![0]);
+        assert_eq!(
+            after(&[cell::<usize>(-7), 3], Instruction::Mod),
+            vec![cell::<usize>(-1)]
+        );
+    }
+
+    #[test]
+    fn division_by_zero_gives_zero() {
+        let (mut vm, output) = vm("70/.70%.@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"0 0 ");
+    }
+
+    #[test]
+    fn greater_than() {
+        assert_eq!(after(&[5, 3], Instruction::GreaterThan), vec![1]);
+        assert_eq!(after(&[3, 5], Instruction::GreaterThan), vec![0]);
+        assert_eq!(after(&[5, 5], Instruction::GreaterThan), vec![0]);
+        assert_eq!(
+            after(&[cell::<usize>(-1), 0], Instruction::GreaterThan),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn arithmetic_takes_the_top_of_the_stack_as_its_right_operand() {
+        let (mut vm, output) = vm("73-.92/.94%.52`.@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"4 4 1 1 ");
+    }
+
+    #[test]
+    fn not() {
+        assert_eq!(after(&[0], Instruction::Not), vec![1]);
+        assert_eq!(after(&[7], Instruction::Not), vec![0]);
+    }
+
+    #[test]
+    fn print_int() {
+        let (mut vm, output) = vm("@", "");
+        vm.stack.push(cell(-12));
+        vm.stack.push(42);
+        vm.consume(Instruction::PrintInt).unwrap();
+        vm.consume(Instruction::PrintInt).unwrap();
+        assert_eq!(output.contents(), b"42 -12 ");
+    }
+
+    #[test]
+    fn print_int_ends_each_number_with_a_space() {
+        let (mut vm, output) = vm("12..@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"2 1 ");
+    }
+
+    #[test]
+    fn print_chr() {
+        let (mut vm, output) = vm("@", "");
+        vm.stack.push(b'A' as usize);
+        vm.consume(Instruction::PrintChr).unwrap();
+        assert_eq!(output.contents(), b"A");
+    }
+
+    #[test]
+    fn read_int() {
+        let (mut vm, _) = vm("@", "ab 12 x34");
+        vm.consume(Instruction::ReadInt).unwrap();
+        vm.consume(Instruction::ReadInt).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[12, 34]);
+        vm.consume(Instruction::ReadInt).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+    }
+
+    #[test]
+    fn read_int_saturates_at_the_largest_cell() {
+        let (mut vm, output) = vm("&.@", "99999999999999999999");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), format!("{} ", i64::MAX).as_bytes());
+        assert_eq!(
+            usize::from_decimal(b"99999999999999999999"),
+            i64::MAX as usize
+        );
+    }
+
+    #[test]
+    fn read_chr() {
+        let (mut vm, _) = vm("@", "A\n");
+        vm.consume(Instruction::ReadChr).unwrap();
+        vm.consume(Instruction::ReadChr).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[65, 10]);
+        vm.consume(Instruction::ReadChr).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+    }
+
+    #[test]
+    fn put() {
+        let (mut vm, _) = vm("@", "");
+        vm.stack.push(b'x' as usize);
+        vm.stack.push(3);
+        vm.stack.push(2);
+        vm.consume(Instruction::Put).unwrap();
+        assert_eq!(vm.space.dims(), (1, 1));
+        let (least, greatest) = vm.space.bounds();
+        assert_eq!((least.0, least.1, greatest.0, greatest.1), (0, 0, 3, 2));
+        assert_eq!(vm.space.get(&Location(3, 2)), b'x' as usize);
+    }
+
+    #[test]
+    fn get() {
+        let (mut vm, _) = vm("ab\ncd", "");
+        vm.stack.push(1);
+        vm.stack.push(1);
+        vm.consume(Instruction::Get).unwrap();
+        vm.stack.push(9);
+        vm.stack.push(9);
+        vm.consume(Instruction::Get).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[b'd' as usize, b' ' as usize]);
+    }
+
+    #[test]
+    fn get_outside_the_program_reads_a_space() {
+        let (mut vm, output) = vm("55g.01-0g.@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"32 32 ");
+    }
+
+    #[test]
+    fn input_file() {
+        let dir = scratch_dir("input");
+        fs::write(dir.join("in.txt"), "AB\nC D\n").unwrap();
+        let (mut vm, _) = vm("@", "");
+        vm.base_dir = dir.clone();
+
+        vm.stack.push_vector(&Location(1, 1));
+        vm.stack.push(0);
+        push_string(&mut vm, "in.txt");
+        vm.consume(Instruction::InputFile).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[3, 2, 1, 1]);
+        assert_eq!(vm.space.get(&Location(2, 1)), b'B' as usize);
+        assert_eq!(vm.space.get(&Location(3, 2)), b'D' as usize);
+
+        push_string(&mut vm, "missing.txt");
+        vm.consume(Instruction::InputFile).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn output_file() {
+        let dir = scratch_dir("output");
+        let (mut vm, _) = vm("ab \ncd \n   ", "");
+        vm.base_dir = dir.clone();
+
+        vm.stack.push_vector(&Location(3, 3));
+        vm.stack.push_vector(&Location(0, 0));
+        vm.stack.push(1);
+        push_string(&mut vm, "out.txt");
+        vm.consume(Instruction::OutputFile).unwrap();
+        assert_eq!(fs::read(dir.join("out.txt")).unwrap(), b"ab\ncd\n");
+        assert_eq!(vm.delta, Direction::East);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn execute_is_refused_without_a_policy() {
+        let (mut vm, _) = vm("@", "");
+        push_string(&mut vm, "true");
+        vm.consume(Instruction::Execute).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+        assert!(vm.stack.as_slice().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn execute_pushes_the_exit_status() {
+        let mut vm = VmBuilder::new()
+            .source("@")
+            .exec_policy(ExecPolicy::new(vec!["false".to_string()]))
+            .build();
+        push_string(&mut vm, "false");
+        vm.consume(Instruction::Execute).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[1]);
+
+        push_string(&mut vm, "true");
+        vm.consume(Instruction::Execute).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn execute_stops_at_the_time_limit() {
+        let mut vm = VmBuilder::new()
+            .source(r#"0"5 peels"=.@"#)
+            .exec_policy(ExecPolicy::new(vec!["sleep".to_string()]))
+            .limits(Limits {
+                max_time: Some(Duration::from_millis(100)),
+                ..Limits::default()
+            })
+            .build();
+        let started = Instant::now();
+        assert!(matches!(vm.run(100), Err(VmError::TimedOut(_))));
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(vm.stack.as_slice(), &[ExecPolicy::TIMED_OUT]);
+    }
+
+    #[test]
+    fn sys_info() {
+        let (mut vm, _) = vm("@", "");
+        vm.stack.push(7);
+        vm.stack.push(2);
+        vm.consume(Instruction::SysInfo).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[7, std::mem::size_of::<usize>()]);
+
+        vm.stack.push(0);
+        vm.consume(Instruction::SysInfo).unwrap();
+        let stack = vm.stack.as_slice();
+        assert_eq!(stack.len(), 2 + 26);
+        assert_eq!(stack[stack.len() - 1], 0b00110);
+        assert_eq!(stack[stack.len() - 7], 2);
+    }
+
+    #[test]
+    fn sys_info_reports_the_furthest_point() {
+        let (mut vm, _) = vm("@", "");
+        vm.space.set(b'X' as usize, Location(i64::MAX, 0)).unwrap();
+        vm.stack.push(19);
+        vm.consume(Instruction::SysInfo).unwrap();
+        vm.stack.push(18);
+        vm.consume(Instruction::SysInfo).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[i64::MAX as usize, 0]);
+    }
+
+    // found by the vm fuzz target, `y` after a `p` at the very edge
+    #[test]
+    fn sys_info_after_a_put_at_the_edge() {
+        let (code, input) = (r#""X"&0p0y@"#, i64::MAX.to_string());
+        let (mut vm, _) = vm(code, &input);
+        assert!(matches!(vm.run(100), Ok(Outcome::Stopped { .. })));
+        let (mut vm, _) = bigint_vm(code, &input);
+        assert!(matches!(vm.run(100), Ok(Outcome::Stopped { .. })));
+    }
+
+    #[test]
+    fn load_semantics() {
+        let (mut vm, _) = vm("@", "");
+        push_fingerprint(&mut vm, "ROMA");
+        vm.consume(Instruction::LoadSemantics).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[fingerprint::ROMA.id(), 1]);
+
+        push_fingerprint(&mut vm, "NULL");
+        vm.consume(Instruction::LoadSemantics).unwrap();
+        assert_eq!(vm.delta, Direction::West, "NULL was not enabled");
+    }
+
+    #[test]
+    fn fingerprint_counts_past_an_id_reflect() {
+        let (mut looping, _) = vm("99*:*:*:*(@", "");
+        assert_eq!(looping.run(100).unwrap(), Outcome::Stopped { ticks: 20 });
+
+        let (mut short, _) = vm("@", "");
+        short.stack.push(7);
+        short.stack.push(2);
+        short.consume(Instruction::LoadSemantics).unwrap();
+        assert_eq!(short.stack.as_slice(), &[7], "popped past the stack");
+        assert_eq!(short.delta, Direction::West);
+    }
+
+    #[test]
+    fn unload_semantics() {
+        let (mut vm, _) = vm("@", "");
+        push_fingerprint(&mut vm, "ROMA");
+        push_fingerprint(&mut vm, "ROMA");
+        vm.consume(Instruction::LoadSemantics).unwrap();
+        vm.stack.pop();
+        vm.stack.pop();
+        vm.consume(Instruction::UnloadSemantics).unwrap();
+        vm.consume(Instruction::Semantic('X')).unwrap();
+        assert!(vm.stack.as_slice().is_empty());
+        assert_eq!(vm.delta, Direction::West);
+    }
+
+    #[test]
+    fn semantic() {
+        let (mut vm, _) = vm("@", "");
+        push_fingerprint(&mut vm, "BOOL");
+        vm.consume(Instruction::LoadSemantics).unwrap();
+        vm.stack.pop();
+        vm.stack.pop();
+
+        vm.stack.push(6);
+        vm.stack.push(3);
+        vm.consume(Instruction::Semantic('X')).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[5]);
+    }
+
+    fn looping(code: &str) -> Option<Cycle> {
+        let mut vm = VmBuilder::new()
+            .source(code)
+            .input(Cursor::new(Vec::new()))
+            .output(SharedBuffer::new())
+            .detect_loops(true)
+            .build();
+        match vm.run(1000) {
+            Err(VmError::Looping(cycle)) => Some(cycle),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn detects_exact_cycles() {
+        let cycle = looping(">v\n^<").unwrap();
+        assert_eq!(cycle.period, 4);
+        assert_eq!(cycle.cells.len(), 4);
+
+        let cycle = looping("1>:v\n ^$<").unwrap();
+        assert_eq!(cycle.period, 6);
+        assert!(cycle.cells.contains(&(3, 1)));
+        assert!(!cycle.cells.contains(&(0, 0)));
+    }
+
+    #[test]
+    fn changing_state_is_not_a_cycle() {
+        // the stack keeps growing
+        assert_eq!(looping(">1v\n^ <"), None);
+        // space keeps being written to
+        assert_eq!(looping(">a99pv\n^    <"), None);
+        // input is read every time around
+        assert_eq!(looping("~"), None);
+    }
+
+    #[test]
+    fn space_wraps_the_ip() {
+        let (mut vm, _) = vm("123\n456", "");
+        vm.delta = Direction::West;
+        vm.next_location();
+        assert_eq!((vm.location.0, vm.location.1), (2, 0));
+        vm.delta = Direction::North;
+        vm.next_location();
+        assert_eq!((vm.location.0, vm.location.1), (2, 1));
+    }
+
+    #[test]
+    fn space_wraps_the_ip_around_everything_written() {
+        let (mut behind, _) = vm("<", "");
+        behind.space.set(b'@' as usize, Location(-3, 0)).unwrap();
+        assert_eq!(behind.run(10).unwrap(), Outcome::Stopped { ticks: 4 });
+        let (mut far, _) = vm("<", "");
+        far.space.set(b'@' as usize, Location(i64::MAX, 0)).unwrap();
+        assert_eq!(far.run(10).unwrap(), Outcome::Stopped { ticks: 2 });
+    }
+
+    #[test]
+    fn space_set_grows_and_keeps_negative_locations() {
+        let mut space = Space::<usize>::new(b"ab");
+        space.set(7, Location(4, 1)).unwrap();
+        assert_eq!(space.dims(), (2, 1));
+        assert_eq!(space.bounds().1 .0, 4);
+        assert_eq!(space.get(&Location(2, 0)), b' ' as usize);
+
+        space.set(9, Location(-1, 0)).unwrap();
+        assert_eq!(space.get(&Location(-1, 0)), 9);
+        assert_eq!(space.get(&Location(4, 0)), b' ' as usize);
+        assert_eq!(space.get(&Location(1, 0)), b'b' as usize);
+        space.set(b' ' as usize, Location(-1, 0)).unwrap();
+        assert_eq!(space.get(&Location(-1, 0)), b' ' as usize);
+
+        let (mut vm, output) = vm("901-0p01-0g.@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.contents(), b"9 ");
+        assert_eq!(vm.space.get(&Location(12, 0)), b'@' as usize);
+    }
+
+    #[test]
+    fn space_respects_its_cell_limit() {
+        let mut space = Space::<usize>::new(b"ab");
+        space.max_cells = Some(4);
+        assert!(space.set(1, Location(1, 1)).is_ok());
+        assert!(space.set(1, Location(1 << 40, 1 << 40)).is_ok());
+        assert_eq!(space.dims(), (2, 1));
+        assert_eq!(space.bounds().1 .1, 1 << 40);
+        assert!(matches!(
+            space.set(1, Location(2, 1)),
+            Err(VmError::SpaceFull(4))
+        ));
+
+        // overwriting a cell needs no more room, and blanking one frees some
+        assert!(space.set(2, Location(1, 1)).is_ok());
+        assert!(space.set(b' ' as usize, Location(0, 0)).is_ok());
+        assert!(space.set(1, Location(2, 1)).is_ok());
+
+        let (mut vm, _) = vm("\"a\"99*:*:*:p@", "");
+        vm.run(100).unwrap();
+        assert_eq!(vm.space.get(&Location(43046721, 43046721)), 97);
+    }
+
+    #[test]
+    fn space_new_handles_line_endings() {
+        let space = Space::<usize>::new(b"ab\r\ncd\re\x0cf\n\xe9");
+        assert_eq!(space.dims(), (2, 4));
+        assert_eq!(space.get(&Location(1, 1)), b'd' as usize);
+        assert_eq!(space.get(&Location(1, 2)), b'f' as usize);
+        assert_eq!(space.get(&Location(0, 3)), 0xe9);
+        assert_eq!(Space::<usize>::new(b"").dims(), (1, 1));
+    }
+
+    #[test]
+    fn stack_pops_zero_when_empty() {
+        let mut stack = Stack::<usize>::new();
+        assert_eq!(stack.pop(), 0);
+        stack.push(3);
+        assert_eq!(stack.pop(), 3);
+        assert_eq!(stack.pop(), 0);
+    }
+
+    #[test]
+    fn stack_strings_and_vectors() {
+        let mut stack = Stack::<usize>::new();
+        stack.push(0);
+        for b in "hi".bytes().rev() {
+            stack.push(b as usize);
+        }
+        assert_eq!(stack.pop_string(), "hi");
+        assert!(stack.as_slice().is_empty());
+
+        stack.push_vector(&Location(-1, 2));
+        assert_eq!(stack.as_slice(), &[usize::MAX, 2]);
+        let v = stack.pop_vector();
+        assert_eq!((v.0, v.1), (-1, 2));
+    }
+
+    #[test]
+    fn narrow_cells_wrap_at_their_width() {
+        assert_eq!(cell::<u32>(-1), u32::MAX);
+        assert_eq!(signed(u32::MAX), -1);
+        assert_eq!(signed(cell::<u16>(-300)), -300);
+        assert_eq!(signed(cell::<i32>(-5)), -5);
+        assert_eq!(signed(usize::MAX), -1);
+    }
+
+    fn bigint_vm(code: &str, input: &str) -> (Vm<BigInt>, SharedBuffer) {
+        let output = SharedBuffer::new();
+        let vm = VmBuilder::new()
+            .source(code)
+            .input(Cursor::new(input.as_bytes().to_vec()))
+            .output(output.clone())
+            .fingerprints(&["BOOL"])
+            .build_with_cells::<BigInt>();
+        (vm, output)
+    }
+
+    #[test]
+    fn bigint_cells_never_overflow() {
+        // 9 squared five times over is 9^32, well past 64 bits
+        let (mut vm, output) = bigint_vm("9:*:*:*:*:*.@", "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"3433683820292512484657849089281 ");
+
+        let (mut vm, output) = bigint_vm("&1+.@", "123456789012345678901234567890");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"123456789012345678901234567891 ");
+    }
+
+    #[test]
+    fn bigint_cells_divide_and_mask_like_signed_integers() {
+        let (mut vm, output) = bigint_vm(r#""LOOB"4($05-3/.05-3%.03-N.@"#, "");
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"-1 -2 2 ");
+    }
+
+    #[test]
+    fn operators_overflow_as_the_policy_says() {
+        let add = |overflow| {
+            ops::NAry::<i8, 2>::add()
+                .overflow(overflow)
+                .eval([100, 100])
+        };
+        assert_eq!(add(Overflow::Wrap), Some(-56));
+        assert_eq!(add(Overflow::Saturate), Some(127));
+        assert_eq!(add(Overflow::Trap), None);
+
+        let div = |overflow| {
+            ops::NAry::<u16, 2>::div()
+                .overflow(overflow)
+                .eval([0x8000, 0xffff])
+        };
+        assert_eq!(div(Overflow::Wrap), Some(0x8000));
+        assert_eq!(div(Overflow::Saturate), Some(0x7fff));
+        assert_eq!(div(Overflow::Trap), None);
+
+        let rem = ops::NAry::<i32, 2>::rem().overflow(Overflow::Trap);
+        assert_eq!(rem.eval([i32::MIN, -1]), Some(0));
+        let mul = ops::NAry::<BigInt, 2>::mul().overflow(Overflow::Trap);
+        assert!(mul
+            .eval([BigInt::from(i64::MAX), BigInt::from(i64::MAX)])
+            .is_some());
+    }
+
+    #[test]
+    fn narrow_cells_trap_on_overflow() {
+        let build = |code: &str| {
+            VmBuilder::new()
+                .source(code)
+                .output(SharedBuffer::new())
+                .overflow(Overflow::Trap)
+                .build_with_cells::<i8>()
+        };
+        assert!(matches!(
+            build("88*1+.@").run(100),
+            Ok(Outcome::Stopped { .. })
+        ));
+        assert!(matches!(
+            build("88*2*.@").run(100),
+            Err(VmError::Overflow(8))
+        ));
+        // bytes past 127 don't fit in the program's cells either
+        assert!(matches!(
+            build("\"\u{e9}\"@").run(100),
+            Err(VmError::Overflow(8))
+        ));
+
+        let mut space = Space::<i8>::new(b"@");
+        space.overflow = Overflow::Saturate;
+        space
+            .load(&[200], &Location(0, 0), true, |_, _| ())
+            .unwrap();
+        assert_eq!(space.get(&Location(0, 0)), 127);
+    }
+
+    #[test]
+    fn narrow_cells_read_input_under_the_policy() {
+        let run = |code: &str, input: &[u8], overflow| {
+            let mut vm = VmBuilder::new()
+                .source(code)
+                .input(Cursor::new(input.to_vec()))
+                .output(SharedBuffer::new())
+                .overflow(overflow)
+                .build_with_cells::<i8>();
+            vm.run(100).map(|_| vm.stack.as_slice().to_vec())
+        };
+        assert_eq!(run("&~@", b"1000\xc8", Overflow::Wrap).unwrap(), [127, -56]);
+        assert_eq!(
+            run("&~@", b"1000\xc8", Overflow::Saturate).unwrap(),
+            [127, 127]
+        );
+        assert!(matches!(
+            run("&@", b"1000", Overflow::Trap),
+            Err(VmError::Overflow(8))
+        ));
+        assert!(matches!(
+            run("~@", b"\xc8", Overflow::Trap),
+            Err(VmError::Overflow(8))
+        ));
+
+        // only what y pushes has to fit
+        assert_eq!(run("2y@", b"", Overflow::Trap).unwrap(), [1]);
+        assert!(matches!(
+            run("3y@", b"", Overflow::Trap),
+            Err(VmError::Overflow(8))
+        ));
+    }
+
+    #[test]
+    fn sys_info_reports_the_cell_size() {
+        let (mut vm, _) = vm("2y@", "");
+        vm.run(100).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[std::mem::size_of::<usize>()]);
+
+        let (mut vm, _) = bigint_vm("2y@", "");
+        vm.run(100).unwrap();
+        assert_eq!(vm.stack.as_slice(), &[BigInt::from(0)]);
+    }
+
+    #[test]
+    fn custom_instructions_come_before_built_in_ones() {
+        let (mut vm, output) = vm(r#"3s.70j@ "s",@"#, "");
+        vm.registry_mut()
+            .register('s', |context| {
+                let a = context.pop();
+                context.push(a * a);
+                Ok(())
+            })
+            .register('j', |context| {
+                let (y, x) = (signed(context.pop()), signed(context.pop()));
+                context.jump(Location(x, y));
+                Ok(())
+            });
+        vm.run(100).unwrap();
+        assert_eq!(output.take(), b"9 s");
+
+        vm.registry_mut().unregister('s');
+        assert!(matches!(decode('s'), Instruction::Reflect));
+        vm.consume(Instruction::Custom('s')).unwrap();
+        assert_eq!(vm.delta, Direction::West);
+    }
+
+    // writes down everything a Vm tells it but the ticks, which it counts
+    #[derive(Default)]
+    struct Recorder {
+        ticks: usize,
+        events: Vec<String>,
+    }
+
+    impl Observer<usize> for Recorder {
+        fn tick(&mut self, _: &Location) {
+            self.ticks += 1;
+        }
+        fn decode(&mut self, at: &Location, instruction: &Instruction) {
+            self.events
+                .push(format!("{:?} at {},{}", instruction, at.0, at.1));
+        }
+        fn push(&mut self, value: &usize) {
+            self.events.push(format!("push {}", value));
+        }
+        fn pop(&mut self, value: &usize) {
+            self.events.push(format!("pop {}", value));
+        }
+        fn read(&mut self, at: &Location, value: &usize) {
+            self.events
+                .push(format!("read {} at {},{}", value, at.0, at.1));
+        }
+        fn write(&mut self, at: &Location, value: &usize) {
+            self.events
+                .push(format!("write {} at {},{}", value, at.0, at.1));
+        }
+        fn output(&mut self, bytes: &[u8]) {
+            self.events
+                .push(format!("output {:?}", String::from_utf8_lossy(bytes)));
+        }
+        fn moved(&mut self, to: &Location, delta: Direction) {
+            self.events
+                .push(format!("move {:?} to {},{}", delta, to.0, to.1));
+        }
+    }
+
+    #[test]
+    fn observers_see_each_step() {
+        let mut vm = VmBuilder::new()
+            .source("591p91g2*.@")
+            .output(SharedBuffer::new())
+            .build_observed(Recorder::default());
+        vm.run(100).unwrap();
+
+        assert_eq!(vm.observer().ticks, 11);
+        assert_eq!(
+            vm.observer().events[..6],
+            [
+                "ReadAndPush(5) at 0,0",
+                "push 5",
+                "move East to 1,0",
+                "ReadAndPush(9) at 1,0",
+                "push 9",
+                "move East to 2,0"
+            ]
+        );
+        assert_eq!(
+            vm.observer().events[9..],
+            [
+                "Put at 3,0",
+                "pop 1",
+                "pop 9",
+                "pop 5",
+                "write 5 at 9,1",
+                "move East to 4,0",
+                "ReadAndPush(9) at 4,0",
+                "push 9",
+                "move East to 5,0",
+                "ReadAndPush(1) at 5,0",
+                "push 1",
+                "move East to 6,0",
+                "Get at 6,0",
+                "pop 1",
+                "pop 9",
+                "read 5 at 9,1",
+                "push 5",
+                "move East to 7,0",
+                "ReadAndPush(2) at 7,0",
+                "push 2",
+                "move East to 8,0",
+                "Mul at 8,0",
+                "pop 2",
+                "pop 5",
+                "push 10",
+                "move East to 9,0",
+                "PrintInt at 9,0",
+                "pop 10",
+                "output \"10 \"",
+                "move East to 10,0",
+                "Stop at 10,0",
+            ]
+        );
     }
 }
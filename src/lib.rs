@@ -0,0 +1,34 @@
+//! A Befunge-93 and Funge-98 interpreter.
+//!
+//! Programs are set up with a [`funge::VmBuilder`] and then driven one
+//! instruction at a time with [`funge::Vm::step`] or to completion with
+//! [`funge::Vm::run`]:
+//!
+//! ```
+//! use rsbefunge::funge::{Outcome, SharedBuffer, VmBuilder};
+//!
+//! let output = SharedBuffer::new();
+//! let mut vm = VmBuilder::new()
+//!     .source(r#"> "!olleH" ,,,,,, @"#)
+//!     .output(output.clone())
+//!     .seed(0)
+//!     .build();
+//! assert!(matches!(vm.run(1000), Ok(Outcome::Stopped { .. })));
+//! assert_eq!(output.take(), b"Hello!");
+//! ```
+
+pub mod asm;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod conformance;
+pub mod coverage;
+pub mod dap;
+pub mod encode;
+pub mod funge;
+pub mod lint;
+pub mod lsp;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod show;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,420 @@
+//! Static checks for Befunge sources.
+//!
+//! [`trace`] walks every path the IP could take from (0, 0), forking where
+//! the direction depends on the stack or on input, and [`lint`] reports
+//! what the walk turns up:
+//!
+//! - cells that no path reaches
+//! - programs with no reachable `@` or `q`
+//! - unknown instructions on a reachable path
+//! - instructions that may pop more than the stack holds
+//! - string mode running off the edge of the program
+//!
+//! The walk reads the program as written, so code that rewrites itself
+//! with `p` may behave differently at run time.
+
+use crate::funge::code::Instruction;
+use crate::funge::{Dialect, Direction, Location, Movable, Space};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+// stack depths at or above this are treated as unknown, and deep enough
+// for anything
+const DEEP: u8 = 64;
+
+// How the walk passed through a cell
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Visit {
+    pub code: bool,   // executed as an instruction
+    pub string: bool, // pushed in string mode
+    headings: u8,     // the directions the IP left the cell in
+}
+
+impl Visit {
+    pub fn reached(&self) -> bool {
+        self.code || self.string
+    }
+
+    pub fn heading(&self, direction: &Direction) -> bool {
+        self.headings & bit(direction) != 0
+    }
+}
+
+fn bit(direction: &Direction) -> u8 {
+    match direction {
+        Direction::North => 1,
+        Direction::East => 2,
+        Direction::South => 4,
+        Direction::West => 8,
+    }
+}
+
+// Every path the IP could take through a program
+#[derive(Debug)]
+pub struct Trace {
+    pub width: usize,
+    pub height: usize,
+    visits: Vec<Visit>,
+    findings: Vec<Finding>,
+}
+
+impl Trace {
+    pub fn visit(&self, x: usize, y: usize) -> Visit {
+        match x < self.width && y < self.height {
+            true => self.visits[y * self.width + x],
+            false => Visit::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Unreachable,
+    NoExit,
+    UnknownInstruction,
+    StackUnderflow,
+    WrappingString,
+}
+
+// Something worth a look at the cell (x, y), counting from 0, and the
+// `len` cells after it on the same row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub x: usize,
+    pub y: usize,
+    pub len: usize,
+    pub kind: Kind,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    // rows and columns count from 1 here, as editors do
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.y + 1, self.x + 1, self.message)
+    }
+}
+
+// where the IP is and what it knows, one of these per pending path
+#[derive(Clone, Copy)]
+struct Ip {
+    x: i64,
+    y: i64,
+    delta: Direction,
+    string_mode: bool,
+    depth: u8,                  // how many cells the stack is known to hold
+    opened: Option<(i64, i64)>, // the `"` that started string mode
+}
+
+pub fn trace(space: &Space<usize>, dialect: Dialect) -> Trace {
+    let (w, h) = space.dims();
+    let mut visits = vec![Visit::default(); w * h];
+    let mut findings = Vec::new();
+
+    // the deepest stack each state has been seen with. A state is only
+    // walked again with a deeper stack, so loops that use the stack up
+    // don't count as underflowing.
+    let mut seen: HashMap<(i64, i64, u8, bool), u8> = HashMap::new();
+    let mut pending = VecDeque::from([Ip {
+        x: 0,
+        y: 0,
+        delta: Direction::East,
+        string_mode: false,
+        depth: 0,
+        opened: None,
+    }]);
+
+    while let Some(mut ip) = pending.pop_front() {
+        let key = (ip.x, ip.y, bit(&ip.delta), ip.string_mode);
+        if seen.get(&key).is_some_and(|depth| *depth >= ip.depth) {
+            continue;
+        }
+        seen.insert(key, ip.depth);
+
+        let at = Location(ip.x, ip.y);
+        let raw = space.get(&at);
+        let instruction = Instruction::from_raw(raw, &ip.string_mode, &dialect);
+        let cell = ip.y as usize * w + ip.x as usize;
+        match ip.string_mode {
+            true => visits[cell].string = true,
+            false => visits[cell].code = true,
+        }
+
+        if !ip.string_mode && is_unknown(raw, &instruction, &dialect) {
+            findings.push(finding(
+                &at,
+                Kind::UnknownInstruction,
+                format!("unknown instruction {:?}", raw as u8 as char),
+            ));
+        }
+
+        let (pops, pushes) = effect(&instruction);
+        if ip.depth < pops && ip.depth < DEEP {
+            findings.push(finding(
+                &at,
+                Kind::StackUnderflow,
+                format!(
+                    "{:?} pops {} but the stack may hold only {}",
+                    raw as u8 as char, pops, ip.depth
+                ),
+            ));
+        }
+        ip.depth = match ip.depth < DEEP && effect_is_known(&instruction) {
+            true => (ip.depth.saturating_sub(pops) + pushes).min(DEEP),
+            false => DEEP,
+        };
+
+        let mut deltas = vec![ip.delta];
+        match instruction {
+            Instruction::Stop | Instruction::Quit => continue,
+            Instruction::StringMode => {
+                ip.string_mode = !ip.string_mode;
+                ip.opened = Some((ip.x, ip.y)).filter(|_| ip.string_mode);
+            }
+            Instruction::Skip => {
+                let mut next = Location(ip.x, ip.y);
+                next.go(&ip.delta);
+                next.constrain(w, h);
+                (ip.x, ip.y) = (next.0, next.1);
+            }
+            Instruction::Reflect => deltas = vec![ip.delta.reverse()],
+            Instruction::Move(direction) => deltas = vec![direction],
+            Instruction::MoveEastOrWest => deltas = vec![Direction::East, Direction::West],
+            Instruction::MoveNorthOrSouth => deltas = vec![Direction::South, Direction::North],
+            Instruction::MoveRandom => {
+                deltas = vec![
+                    Direction::North,
+                    Direction::East,
+                    Direction::South,
+                    Direction::West,
+                ]
+            }
+            // these reflect when they fail
+            Instruction::ReadInt
+            | Instruction::ReadChr
+            | Instruction::InputFile
+            | Instruction::OutputFile
+            | Instruction::Execute
+            | Instruction::LoadSemantics
+            | Instruction::UnloadSemantics
+            | Instruction::Semantic(_) => deltas.push(ip.delta.reverse()),
+            _ => (),
+        }
+
+        for delta in deltas {
+            visits[cell].headings |= bit(&delta);
+
+            let mut next = Location(ip.x, ip.y);
+            next.go(&delta);
+            let wrapped = next.0 < 0 || next.1 < 0 || next.0 >= w as i64 || next.1 >= h as i64;
+            next.constrain(w, h);
+
+            if wrapped && ip.string_mode {
+                if let Some((x, y)) = ip.opened {
+                    findings.push(finding(
+                        &Location(x, y),
+                        Kind::WrappingString,
+                        "string mode runs off the edge of the program".to_string(),
+                    ));
+                }
+            }
+
+            pending.push_back(Ip {
+                x: next.0,
+                y: next.1,
+                delta,
+                ..ip
+            });
+        }
+    }
+
+    findings.sort_by_key(|f| (f.y, f.x));
+    findings.dedup_by(|a, b| a.x == b.x && a.y == b.y && a.kind == b.kind);
+    Trace {
+        width: w,
+        height: h,
+        visits,
+        findings,
+    }
+}
+
+// Lints a program, findings come back in reading order
+pub fn lint(code: &[u8], dialect: Dialect) -> Vec<Finding> {
+    let space = Space::<usize>::new(code);
+    let trace = trace(&space, dialect);
+    let mut findings = trace.findings.clone();
+
+    let exits = (0..trace.height)
+        .flat_map(|y| (0..trace.width).map(move |x| (x, y)))
+        .filter(|(x, y)| trace.visit(*x, *y).code)
+        .any(|(x, y)| {
+            let raw = space.get(&Location(x as i64, y as i64));
+            matches!(
+                Instruction::from_raw(raw, &false, &dialect),
+                Instruction::Stop | Instruction::Quit
+            )
+        });
+    if !exits {
+        findings.push(Finding {
+            x: 0,
+            y: 0,
+            len: 1,
+            kind: Kind::NoExit,
+            message: "no reachable @ or q, the program never stops".to_string(),
+        });
+    }
+
+    // runs of unreached cells on a row are reported together
+    for y in 0..trace.height {
+        let mut x = 0;
+        while x < trace.width {
+            let unreached = |x: usize| {
+                !trace.visit(x, y).reached()
+                    && space.get(&Location(x as i64, y as i64)) != b' ' as usize
+            };
+            if !unreached(x) {
+                x += 1;
+                continue;
+            }
+
+            let start = x;
+            while x < trace.width && unreached(x) {
+                x += 1;
+            }
+            findings.push(Finding {
+                x: start,
+                y,
+                len: x - start,
+                kind: Kind::Unreachable,
+                message: match x - start {
+                    1 => "unreachable cell".to_string(),
+                    n => format!("{} unreachable cells", n),
+                },
+            });
+        }
+    }
+
+    findings.sort_by_key(|f| (f.y, f.x));
+    findings
+}
+
+fn finding(at: &Location, kind: Kind, message: String) -> Finding {
+    Finding {
+        x: at.0 as usize,
+        y: at.1 as usize,
+        len: 1,
+        kind,
+        message,
+    }
+}
+
+// characters that only reflect (or in Befunge-93, push themselves)
+// because they aren't instructions at all
+fn is_unknown(raw: usize, instruction: &Instruction, dialect: &Dialect) -> bool {
+    match (dialect, instruction) {
+        (Dialect::Befunge98, Instruction::Reflect) => raw != b'r' as usize,
+        (Dialect::Befunge93, Instruction::ReadAndPush(_)) => !(raw as u8).is_ascii_digit(),
+        _ => false,
+    }
+}
+
+// how many cells an instruction pops and pushes
+fn effect(instruction: &Instruction) -> (u8, u8) {
+    match instruction {
+        Instruction::ReadAndPush(_) | Instruction::ReadInt | Instruction::ReadChr => (0, 1),
+        Instruction::Quit
+        | Instruction::MoveEastOrWest
+        | Instruction::MoveNorthOrSouth
+        | Instruction::Pop
+        | Instruction::PrintInt
+        | Instruction::PrintChr
+        | Instruction::SysInfo => (1, 0),
+        Instruction::Duplicate => (1, 2),
+        Instruction::Swap => (2, 2),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::GreaterThan
+        | Instruction::Get => (2, 1),
+        Instruction::Not => (1, 1),
+        Instruction::Put => (3, 0),
+        _ => (0, 0),
+    }
+}
+
+// false for instructions that pop or push a number of cells that depends
+// on what's on the stack
+fn effect_is_known(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::SysInfo
+            | Instruction::InputFile
+            | Instruction::OutputFile
+            | Instruction::Execute
+            | Instruction::LoadSemantics
+            | Instruction::UnloadSemantics
+            | Instruction::Semantic(_)
+            | Instruction::Custom(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(code: &str) -> Vec<(usize, usize, Kind)> {
+        lint(code.as_bytes(), Dialect::Befunge98)
+            .into_iter()
+            .map(|f| (f.x, f.y, f.kind))
+            .collect()
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        lint(code.as_bytes(), Dialect::Befunge98)
+            .into_iter()
+            .map(|f| f.message)
+            .collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        assert_eq!(kinds("\"!ih\",,,@"), vec![]);
+        assert_eq!(kinds("0\"olleh\">:#,_@"), vec![]);
+    }
+
+    #[test]
+    fn unreachable_cells() {
+        assert_eq!(kinds("1.@ 23"), vec![(4, 0, Kind::Unreachable)]);
+        assert_eq!(messages("@\n12"), vec!["2 unreachable cells"]);
+    }
+
+    #[test]
+    fn no_exit() {
+        assert_eq!(kinds(">1v\n^ <"), vec![(0, 0, Kind::NoExit)]);
+    }
+
+    #[test]
+    fn forks_follow_both_branches() {
+        assert_eq!(kinds("1v\n@_@"), vec![]);
+        assert_eq!(kinds("1v\n @\n >@"), vec![(1, 2, Kind::Unreachable)]);
+        assert_eq!(kinds("v\n_@"), vec![(0, 1, Kind::StackUnderflow)]);
+    }
+
+    #[test]
+    fn unknown_instructions() {
+        assert_eq!(kinds("1{@"), vec![(1, 0, Kind::UnknownInstruction)]);
+        assert_eq!(lint(b"1{.@", Dialect::Befunge93).len(), 1);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert_eq!(kinds("1+.@"), vec![(1, 0, Kind::StackUnderflow)]);
+        assert_eq!(kinds("12+.@"), vec![]);
+    }
+
+    #[test]
+    fn wrapping_string() {
+        assert_eq!(kinds("\"ab@"), vec![(0, 0, Kind::WrappingString)]);
+    }
+}
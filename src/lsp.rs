@@ -0,0 +1,659 @@
+//! A Language Server Protocol server for Befunge sources.
+//!
+//! [`serve`] speaks JSON-RPC over a pair of streams, normally stdin and
+//! stdout, and offers:
+//!
+//! - hover docs for the instruction under the cursor
+//! - diagnostics from [`crate::lint`], refreshed on every change
+//! - go to definition, which follows the IP from the cursor to where its
+//!   path leads: the next branch, `@` or `q`, or the cell that closes a loop
+//! - semantic tokens for string mode, movement, arithmetic and the rest
+//!
+//! Documents are synced in full. Positions count bytes when the client
+//! offers the utf-8 position encoding, so columns line up with cells, and
+//! UTF-16 code units, the protocol's default, when it doesn't.
+
+use crate::funge::code::{Category, Instruction};
+use crate::funge::{split_lines, Dialect, Direction, Location, Movable, Space};
+use crate::lint::{self, Kind};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+// the largest message body we read, in bytes
+const MAX_BODY: usize = 64 << 20;
+
+// the semantic token types we use, a token's type is its index in here
+const TOKEN_TYPES: [&str; 7] = [
+    "string", "number", "keyword", "macro", "operator", "variable", "function",
+];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        dialect: Dialect::default(),
+        encoding: Encoding::Utf16,
+    };
+
+    while let Some(body) = read_body(&mut input)? {
+        let message = body
+            .map_err(|problem| (INVALID_REQUEST, problem))
+            .and_then(|body| {
+                serde_json::from_slice::<Value>(&body).map_err(|err| (PARSE_ERROR, err.to_string()))
+            });
+        let message = match message {
+            Ok(message) => message,
+            Err((code, problem)) => {
+                let reply = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": code, "message": problem },
+                });
+                write_message(&mut server.output, &reply)?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or("");
+        if method == "exit" {
+            break;
+        }
+        server.handle(method, &message)?;
+    }
+    Ok(())
+}
+
+struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, String>,
+    dialect: Dialect,
+    encoding: Encoding,
+}
+
+// what a position's character counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,  // bytes, one per cell
+    Utf16, // UTF-16 code units
+}
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, method: &str, message: &Value) -> io::Result<()> {
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Some(self.initialize(params)),
+            "shutdown" => Some(Value::Null),
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                return self.update(uri, text.to_string());
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let changes = params["contentChanges"].as_array();
+                return match changes.and_then(|c| c.last()) {
+                    Some(change) => {
+                        let text = change["text"].as_str().unwrap_or("");
+                        self.update(uri, text.to_string())
+                    }
+                    None => Ok(()),
+                };
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                self.documents.remove(uri);
+                return self.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+            }
+            "textDocument/hover" => {
+                Some(self.with_cell(params, |s, text, space, x, y| s.hover(text, space, x, y)))
+            }
+            "textDocument/definition" => {
+                let uri = params["textDocument"]["uri"].clone();
+                Some(self.with_cell(params, |s, text, space, x, y| {
+                    let targets: Vec<Value> = s
+                        .destinations(space, x, y)
+                        .into_iter()
+                        .map(|(x, y)| {
+                            json!({ "uri": uri, "range": range(text, x, y, 1, s.encoding) })
+                        })
+                        .collect();
+                    json!(targets)
+                }))
+            }
+            "textDocument/semanticTokens/full" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let data = match self.documents.get(uri) {
+                    Some(text) => semantic_tokens(text, self.dialect, self.encoding),
+                    None => Vec::new(),
+                };
+                Some(json!({ "data": data }))
+            }
+            _ => None,
+        };
+
+        // only requests have an id, notifications get no reply
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return Ok(()),
+        };
+        let reply = match result {
+            Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": METHOD_NOT_FOUND, "message": format!("unknown method {}", method) },
+            }),
+        };
+        write_message(&mut self.output, &reply)
+    }
+
+    // settles on a position encoding, and says what the server can do
+    fn initialize(&mut self, params: &Value) -> Value {
+        let offered = &params["capabilities"]["general"]["positionEncodings"];
+        if offered
+            .as_array()
+            .is_some_and(|e| e.contains(&json!("utf-8")))
+        {
+            self.encoding = Encoding::Utf8;
+        }
+        json!({
+            "capabilities": {
+                "positionEncoding": match self.encoding {
+                    Encoding::Utf8 => "utf-8",
+                    Encoding::Utf16 => "utf-16",
+                },
+                "textDocumentSync": 1,
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "semanticTokensProvider": {
+                    "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                    "full": true,
+                },
+            },
+            "serverInfo": { "name": "rsbefunge" },
+        })
+    }
+    fn update(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let diagnostics: Vec<Value> = lint::lint(text.as_bytes(), self.dialect)
+            .into_iter()
+            .map(|finding| {
+                let (severity, tags) = match finding.kind {
+                    Kind::Unreachable => (3, json!([1])), // information, unnecessary
+                    _ => (2, json!([])),                  // warning
+                };
+                json!({
+                    "range": range(&text, finding.x, finding.y, finding.len, self.encoding),
+                    "severity": severity,
+                    "tags": tags,
+                    "source": "rsbefunge",
+                    "message": finding.message,
+                })
+            })
+            .collect();
+
+        self.documents.insert(uri.to_string(), text);
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        write_message(
+            &mut self.output,
+            &json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+        )
+    }
+
+    // runs `f` on the cell at the request's position, or gives null if the
+    // document isn't open
+    fn with_cell(
+        &self,
+        params: &Value,
+        f: impl Fn(&Self, &str, &Space<usize>, usize, usize) -> Value,
+    ) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        let y = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        match self.documents.get(uri) {
+            Some(text) => {
+                let x = cell(text, y, character, self.encoding);
+                f(self, text, &Space::new(text.as_bytes()), x, y)
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, text: &str, space: &Space<usize>, x: usize, y: usize) -> Value {
+        let (w, h) = space.dims();
+        if x >= w || y >= h {
+            return Value::Null;
+        }
+
+        let raw = space.get(&Location(x as i64, y as i64));
+        let shown = raw as u8 as char;
+        let trace = lint::trace(space, self.dialect);
+        let visit = trace.visit(x, y);
+        let value = match visit.string && !visit.code {
+            true => format!("**`{}`** pushes {} in string mode", shown, raw),
+            false => {
+                let instruction = Instruction::from_raw(raw, &false, &self.dialect);
+                format!(
+                    "**`{}`** {:?}\n\n{}",
+                    shown,
+                    instruction,
+                    instruction.describe()
+                )
+            }
+        };
+        json!({
+            "contents": { "kind": "markdown", "value": value },
+            "range": range(text, x, y, 1, self.encoding),
+        })
+    }
+
+    // where the IP goes from (x, y), following each way it could leave the cell
+    fn destinations(&self, space: &Space<usize>, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let raw = space.get(&Location(x as i64, y as i64));
+        let trace = lint::trace(space, self.dialect);
+        let visit = trace.visit(x, y);
+
+        let mut directions: Vec<Direction> = [
+            Direction::North,
+            Direction::East,
+            Direction::South,
+            Direction::West,
+        ]
+        .into_iter()
+        .filter(|d| visit.heading(d))
+        .collect();
+        if directions.is_empty() {
+            directions = match Instruction::from_raw(raw, &false, &self.dialect) {
+                Instruction::Move(direction) => vec![direction],
+                Instruction::MoveEastOrWest => vec![Direction::East, Direction::West],
+                Instruction::MoveNorthOrSouth => vec![Direction::South, Direction::North],
+                _ => vec![Direction::East],
+            };
+        }
+
+        let mut found = Vec::new();
+        for direction in directions {
+            let destination = self.follow(space, x, y, direction);
+            if !found.contains(&destination) {
+                found.push(destination);
+            }
+        }
+        found
+    }
+
+    // follows the IP leaving (x, y) heading `delta` until it needs the stack
+    // or input to decide where to go, stops, or comes back around
+    fn follow(&self, space: &Space<usize>, x: usize, y: usize, delta: Direction) -> (usize, usize) {
+        let (w, h) = space.dims();
+        let (mut at, mut delta, mut string_mode) = (Location(x as i64, y as i64), delta, false);
+        let mut seen = HashSet::new();
+        loop {
+            at.go(&delta);
+            at.constrain(w, h);
+            if !seen.insert((at.0, at.1, delta, string_mode)) {
+                return (at.0 as usize, at.1 as usize);
+            }
+
+            match Instruction::from_raw(space.get(&at), &string_mode, &self.dialect) {
+                Instruction::StringMode => string_mode = !string_mode,
+                Instruction::Move(direction) => delta = direction,
+                Instruction::Reflect => delta = delta.reverse(),
+                Instruction::Skip => at.go(&delta),
+                Instruction::MoveEastOrWest
+                | Instruction::MoveNorthOrSouth
+                | Instruction::MoveRandom
+                | Instruction::Stop
+                | Instruction::Quit => return (at.0 as usize, at.1 as usize),
+                _ => (),
+            }
+        }
+    }
+}
+
+// the LSP encoding of semantic tokens: five numbers per token, the line and
+// start relative to the previous token, the length, the type and modifiers
+fn semantic_tokens(text: &str, dialect: Dialect, encoding: Encoding) -> Vec<u32> {
+    let space = Space::<usize>::new(text.as_bytes());
+    let trace = lint::trace(&space, dialect);
+    let (w, h) = space.dims();
+
+    let mut data = Vec::new();
+    let (mut last_line, mut last_start) = (0, 0);
+    for y in 0..h {
+        let mut x = 0;
+        while x < w {
+            let kind = token_type(&space, &trace, x, y, dialect);
+            let start = x;
+            while x < w && token_type(&space, &trace, x, y, dialect) == kind {
+                x += 1;
+            }
+            let kind = match kind {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let end = character(text, y, x, encoding) as u32;
+            let (line, start) = (y as u32, character(text, y, start, encoding) as u32);
+            let delta_start = match line == last_line {
+                true => start - last_start,
+                false => start,
+            };
+            data.extend([line - last_line, delta_start, end - start, kind, 0]);
+            (last_line, last_start) = (line, start);
+        }
+    }
+    data
+}
+
+fn token_type(
+    space: &Space<usize>,
+    trace: &lint::Trace,
+    x: usize,
+    y: usize,
+    dialect: Dialect,
+) -> Option<u32> {
+    let raw = space.get(&Location(x as i64, y as i64));
+    let visit = trace.visit(x, y);
+    if raw == b'"' as usize || (visit.string && !visit.code) {
+        return Some(0);
+    }
+
+    let instruction = Instruction::from_raw(raw, &false, &dialect);
+    match (instruction.category(), &instruction) {
+        (_, Instruction::NoOp) => None,
+        (_, Instruction::Reflect) if raw != b'r' as usize => None,
+        (Category::Literal, _) => Some(1),
+        (Category::Movement, _) => Some(2),
+        (Category::Flow, _) => Some(3),
+        (Category::Arithmetic, _) => Some(4),
+        (Category::Stack | Category::Memory, _) => Some(5),
+        (Category::Io | Category::System | Category::Fingerprint, _) => Some(6),
+    }
+}
+
+fn range(text: &str, x: usize, y: usize, len: usize, encoding: Encoding) -> Value {
+    json!({
+        "start": { "line": y, "character": character(text, y, x, encoding) },
+        "end": { "line": y, "character": character(text, y, x + len, encoding) },
+    })
+}
+
+// the bytes of line `y`, without its line break, splitting lines the way
+// funge space does
+fn line(text: &str, y: usize) -> &[u8] {
+    split_lines(text.as_bytes()).get(y).copied().unwrap_or(b"")
+}
+
+// the position's character for cell `x` of line `y`, counting any cells
+// past the end of the line as one unit each
+fn character(text: &str, y: usize, x: usize, encoding: Encoding) -> usize {
+    let line = line(text, y);
+    match encoding {
+        Encoding::Utf8 => x,
+        Encoding::Utf16 => {
+            let within = &line[..x.min(line.len())];
+            let units = String::from_utf8_lossy(within).encode_utf16().count();
+            units + x.saturating_sub(line.len())
+        }
+    }
+}
+
+// the cell for a position's character on line `y`, the inverse of
+// `character`
+fn cell(text: &str, y: usize, character: usize, encoding: Encoding) -> usize {
+    let line = line(text, y);
+    match encoding {
+        Encoding::Utf8 => character,
+        Encoding::Utf16 => {
+            let mut units = 0;
+            for (x, c) in String::from_utf8_lossy(line).char_indices() {
+                if units >= character {
+                    return x;
+                }
+                units += c.len_utf16();
+            }
+            line.len() + character.saturating_sub(units)
+        }
+    }
+}
+
+// Reads a message framed with a Content-Length header, None at the end of
+// the input. The debug adapter uses the same framing.
+pub(crate) fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    match read_body(input)? {
+        Some(Ok(body)) => serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Some(Err(problem)) => Err(io::Error::new(io::ErrorKind::InvalidData, problem)),
+        None => Ok(None),
+    }
+}
+
+// The body of a message, unparsed, or why it was skipped. A body over
+// MAX_BODY is read past rather than held, so the next message can follow.
+fn read_body(input: &mut impl BufRead) -> io::Result<Option<Result<Vec<u8>, String>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length",
+        )
+    })?;
+    if length > MAX_BODY {
+        io::copy(
+            &mut io::Read::take(&mut *input, length as u64),
+            &mut io::sink(),
+        )?;
+        let problem = format!("message of {} bytes is over {}", length, MAX_BODY);
+        return Ok(Some(Err(problem)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(Ok(body)))
+}
+
+pub(crate) fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // runs a session and returns everything the server sent back
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut replies = Vec::new();
+        let mut output = Cursor::new(output);
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.b98", "text": text } },
+        })
+    }
+
+    fn request(id: u64, method: &str, x: usize, y: usize) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": "file:///a.b98" },
+                "position": { "line": y, "character": x },
+            },
+        })
+    }
+
+    #[test]
+    fn publishes_diagnostics_on_open() {
+        let replies = session(&[open("1.@ 2")]);
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 4);
+        assert_eq!(diagnostics[0]["message"], "unreachable cell");
+    }
+
+    #[test]
+    fn hover_describes_the_instruction() {
+        let replies = session(&[
+            open("\"a\"+@"),
+            request(1, "textDocument/hover", 3, 0),
+            request(2, "textDocument/hover", 1, 0),
+        ]);
+        let add = replies[1]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(add.contains("push b + a"));
+        let string = replies[2]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(string.contains("pushes 97 in string mode"));
+    }
+
+    #[test]
+    fn definition_follows_the_ip() {
+        let replies = session(&[
+            open(">  v\n1\n   >@"),
+            request(1, "textDocument/definition", 0, 0),
+        ]);
+        assert_eq!(replies[1]["result"][0]["range"]["start"]["character"], 4);
+        assert_eq!(replies[1]["result"][0]["range"]["start"]["line"], 2);
+    }
+
+    #[test]
+    fn semantic_tokens_cover_strings_and_arrows() {
+        let tokens = semantic_tokens(">\"ab\"1+@", Dialect::Befunge98, Encoding::Utf8);
+        assert_eq!(
+            tokens,
+            vec![0, 0, 1, 2, 0, 0, 1, 4, 0, 0, 0, 4, 1, 1, 0, 0, 1, 1, 4, 0, 0, 1, 1, 3, 0]
+        );
+    }
+
+    #[test]
+    fn positions_count_utf16_unless_utf8_is_offered() {
+        let initialize = |encodings: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": { "capabilities": { "general": { "positionEncodings": encodings } } },
+            })
+        };
+        // "é" is two cells, and one UTF-16 unit
+        let source = "\"\u{e9}\"@ 1";
+
+        let replies = session(&[
+            initialize(json!(["utf-16"])),
+            open(source),
+            request(1, "textDocument/hover", 3, 0),
+        ]);
+        assert_eq!(
+            replies[0]["result"]["capabilities"]["positionEncoding"],
+            "utf-16"
+        );
+        assert_eq!(
+            replies[1]["params"]["diagnostics"][0]["range"]["start"]["character"],
+            5
+        );
+        assert!(replies[2]["result"]["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("Stop"));
+
+        let replies = session(&[
+            initialize(json!(["utf-16", "utf-8"])),
+            open(source),
+            request(1, "textDocument/hover", 4, 0),
+        ]);
+        assert_eq!(
+            replies[0]["result"]["capabilities"]["positionEncoding"],
+            "utf-8"
+        );
+        assert_eq!(
+            replies[1]["params"]["diagnostics"][0]["range"]["start"]["character"],
+            6
+        );
+        assert!(replies[2]["result"]["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("Stop"));
+    }
+
+    #[test]
+    fn keeps_serving_after_a_malformed_message() {
+        let mut input = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+        write_message(&mut input, &request(1, "textDocument/rename", 0, 0)).unwrap();
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let parse = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(parse["error"]["code"], PARSE_ERROR);
+        assert_eq!(parse["id"], Value::Null);
+        let next = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(next["id"], 1);
+    }
+
+    #[test]
+    fn refuses_messages_that_are_too_large() {
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let reply = read_message(&mut Cursor::new(output)).unwrap().unwrap();
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn lines_end_where_funge_space_ends_them() {
+        let text = "ab\r\u{e9}x\r\ny\nz";
+        assert_eq!(line(text, 1), "\u{e9}x".as_bytes());
+        assert_eq!(line(text, 3), b"z");
+        assert_eq!(character(text, 1, 2, Encoding::Utf16), 1);
+        assert_eq!(cell(text, 1, 1, Encoding::Utf16), 2);
+    }
+
+    #[test]
+    fn unknown_requests_get_an_error() {
+        let replies = session(&[request(7, "textDocument/rename", 0, 0)]);
+        assert_eq!(replies[0]["error"]["code"], METHOD_NOT_FOUND);
+    }
+}
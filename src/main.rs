@@ -1,56 +1,657 @@
-mod funge;
-
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use num_bigint::BigInt;
+use rsbefunge::{asm, conformance, coverage, dap, encode, funge, lint, lsp, show};
 use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    #[arg(short = 't', value_name = "CODE_PATH", default_value = "./test.b98")]
-    pub target: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Program files, stacked top to bottom in the order given. `-` reads stdin
+    #[arg(value_name = "FILE", required_unless_present_any = ["eval", "target"])]
+    pub files: Vec<String>,
+
+    // the old way of naming the program, still taken but no longer shown
+    #[arg(short = 't', value_name = "FILE", hide = true, conflicts_with = "eval")]
+    pub target: Option<String>,
 
-    #[arg(short = 's', value_name = "MAX_TICKS", default_value = "100")]
+    /// Run CODE instead of a file, may be repeated to add more lines
+    #[arg(short = 'e', value_name = "CODE", conflicts_with = "files")]
+    pub eval: Vec<String>,
+
+    /// Stop after MAX_TICKS ticks, 0 runs until the program stops
+    #[arg(short = 's', value_name = "MAX_TICKS", default_value = "0")]
     pub stop_after: usize,
 
     #[arg(short = 'v', default_value_t = false)]
     pub visual: bool,
+
+    /// Report how the run went on stderr
+    #[arg(long = "stats", default_value_t = false)]
+    pub stats: bool,
+
+    /// Stop as soon as the program is caught in a loop it can never leave
+    #[arg(long = "detect-loops", default_value_t = false)]
+    pub detect_loops: bool,
+
+    /// Add which cells ran and which way branches went to the report in FILE
+    #[arg(long = "coverage", value_name = "FILE")]
+    pub coverage: Option<PathBuf>,
+
+    /// Let `=` run COMMAND, may be repeated. `=` is disabled unless given
+    #[arg(long = "allow-exec", value_name = "COMMAND")]
+    pub allow_exec: Vec<String>,
+
+    /// Working directory for commands run by `=`
+    #[arg(long = "exec-dir", value_name = "DIR", requires = "allow_exec")]
+    pub exec_dir: Option<PathBuf>,
+
+    /// Kill commands run by `=` after this many seconds
+    #[arg(long = "exec-timeout", value_name = "SECS", requires = "allow_exec")]
+    pub exec_timeout: Option<u64>,
+
+    /// Fail if the stack grows deeper than this many cells
+    #[arg(long = "max-stack", value_name = "CELLS")]
+    pub max_stack: Option<usize>,
+
+    /// Fail if funge space would hold more than this many cells that aren't
+    /// spaces, 0 for no limit
+    #[arg(long = "max-cells", value_name = "CELLS", default_value_t = funge::Limits::DEFAULT_MAX_CELLS)]
+    pub max_cells: usize,
+
+    /// Fail if the program writes more than this many bytes
+    #[arg(long = "max-output", value_name = "BYTES")]
+    pub max_output: Option<usize>,
+
+    /// Fail if the program runs for longer than this many seconds, checked
+    /// between steps and while `=` waits, but not during a blocked read
+    #[arg(long = "max-time", value_name = "SECS")]
+    pub max_time: Option<u64>,
+
+    /// What the stack and funge space hold
+    #[arg(long = "cells", value_enum, default_value_t = Cells::Fixed)]
+    pub cells: Cells,
+
+    /// Use signed cells this many bits wide rather than machine words
+    #[arg(
+        long = "cell-bits",
+        value_name = "BITS",
+        value_parser = ["8", "16", "32", "64", "128"],
+        conflicts_with = "cells"
+    )]
+    pub cell_bits: Option<String>,
+
+    /// What arithmetic does when a result doesn't fit in a cell
+    #[arg(long = "overflow", value_enum, default_value_t = Overflow::Wrap)]
+    pub overflow: Overflow,
+
+    /// Run as Befunge-93 rather than Funge-98, with only its instructions
+    #[arg(long = "befunge93", default_value_t = false)]
+    pub befunge93: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Cells {
+    /// Machine words that wrap on overflow
+    Fixed,
+    /// Integers that never overflow, for a price in speed
+    Bigint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Keep the low bits, as two's complement
+    Wrap,
+    /// Clamp to the smallest or largest cell
+    Saturate,
+    /// Stop with an error
+    Trap,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check programs against the .in, .out and .exit files next to them
+    Test(TestArgs),
+    /// Look for unreachable code, missing exits and other likely mistakes
+    Lint(LintArgs),
+    /// Lay out a linear assembly as a Funge-98 program
+    Asm(AsmArgs),
+    /// Find the shortest code that pushes numbers or text
+    Encode(EncodeArgs),
+    /// Print programs with their instructions coloured by what they do
+    #[command(alias = "cat")]
+    Show(ShowArgs),
+    /// Run a language server for editors, speaking LSP over stdin and stdout
+    Lsp,
+    /// Run a debug adapter for editors, speaking DAP over stdin and stdout
+    Dap,
+}
+
+#[derive(Debug, Args)]
+pub struct TestArgs {
+    /// Programs, or directories to search for them
+    #[arg(value_name = "PATH", default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    #[arg(short = 's', value_name = "MAX_TICKS", default_value = "1000000")]
+    pub stop_after: usize,
+
+    /// Seed for `?`, the same seed always gives the same run
+    #[arg(long = "seed", default_value_t = 0)]
+    pub seed: u64,
+
+    /// Add which cells ran and which way branches went to the report in FILE
+    #[arg(long = "coverage", value_name = "FILE")]
+    pub coverage: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct LintArgs {
+    /// Programs to check
+    #[arg(value_name = "FILE", required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Check them as Befunge-93 rather than Funge-98 programs
+    #[arg(long = "befunge93", default_value_t = false)]
+    pub befunge93: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AsmArgs {
+    /// The assembly to lay out
+    #[arg(value_name = "FILE")]
+    pub file: PathBuf,
+
+    /// Write the program to FILE instead of stdout
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Write a JSON map from assembly lines to the cells they became to FILE
+    #[arg(long = "map", value_name = "FILE")]
+    pub map: Option<PathBuf>,
+
+    /// Input to give the program while checking the layout
+    #[arg(long = "input", value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// Don't check the layout by running it
+    #[arg(long = "no-verify", default_value_t = false)]
+    pub no_verify: bool,
+
+    /// How many steps of the assembly to run while checking the layout
+    #[arg(short = 's', value_name = "MAX_STEPS", default_value = "1000000")]
+    pub stop_after: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct EncodeArgs {
+    /// Numbers, or with --string text, to encode, one line of code each
+    #[arg(value_name = "VALUE", required = true, allow_negative_numbers = true)]
+    pub values: Vec<String>,
+
+    /// Push each value's characters instead of reading it as a number
+    #[arg(long = "string", default_value_t = false)]
+    pub string: bool,
+
+    /// Only use what Befunge-93 has, the digits 0-9
+    #[arg(long = "befunge93", default_value_t = false)]
+    pub befunge93: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ShowArgs {
+    /// Programs to print
+    #[arg(value_name = "FILE", required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Number the rows and columns
+    #[arg(long = "rulers", default_value_t = false)]
+    pub rulers: bool,
+
+    /// Draw the paths the IP can take and dim the cells it never reaches
+    #[arg(long = "path", default_value_t = false)]
+    pub path: bool,
+
+    /// Print without colours, the default when stdout isn't a terminal
+    #[arg(long = "no-colour", default_value_t = false)]
+    pub no_colour: bool,
+}
+
+// Exit statuses, besides 0 when the program stops with `@` and whatever it
+// pops when it quits with `q`. The interpreter's own are kept well above
+// the small numbers programs tend to quit with, but `q` can still pick any
+// status, so `--stats` is the way to be sure. `test` and `lint` never exit
+// with a program's status.
+const EXIT_OUT_OF_TICKS: i32 = 240;
+const EXIT_TESTS_FAILED: i32 = 1;
+const EXIT_LINT_FINDINGS: i32 = 1;
+const EXIT_NO_INPUT: i32 = 66;
+#[cfg(not(feature = "visual"))]
+const EXIT_USAGE: i32 = 64;
+
+// exit statuses for runtime errors, one per kind of error
+fn exit_code(err: &funge::VmError) -> i32 {
+    match err {
+        funge::VmError::StackOverflow(_) => 241,
+        funge::VmError::SpaceFull(_) => 242,
+        funge::VmError::TooMuchOutput(_) => 243,
+        funge::VmError::TimedOut(_) => 244,
+        funge::VmError::Looping(_) => 245,
+        funge::VmError::Overflow(_) => 246,
+    }
 }
 
 #[allow(dead_code)]
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Some(target) = cli.target.take() {
+        eprintln!("Warning: -t is deprecated, name the program without it");
+        cli.files.insert(0, target);
+    }
     //println!("{:?}", cli);
     //if cli.stop_after == funge::Vm::FOREVER {
     //    return;
     //}
 
+    match &cli.command {
+        Some(Command::Test(args)) => process::exit(run_tests(args)),
+        Some(Command::Lint(args)) => process::exit(run_lint(args)),
+        Some(Command::Asm(args)) => process::exit(run_asm(args)),
+        Some(Command::Encode(args)) => process::exit(run_encode(args)),
+        Some(Command::Show(args)) => process::exit(run_show(args)),
+        Some(Command::Lsp) => {
+            if let Err(err) = lsp::serve(io::stdin().lock(), io::stdout().lock()) {
+                eprintln!("Error: {}", err);
+                process::exit(EXIT_NO_INPUT);
+            }
+            return;
+        }
+        Some(Command::Dap) => {
+            if let Err(err) = dap::serve(io::stdin().lock(), io::stdout().lock()) {
+                eprintln!("Error: {}", err);
+                process::exit(EXIT_NO_INPUT);
+            }
+            return;
+        }
+        None => (),
+    }
+
+    let program = match load_program(&cli) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(EXIT_NO_INPUT);
+        }
+    };
+
     if !cli.visual {
-        run_vm(&cli);
+        run_vm(&cli, program);
     } else {
-        visual::run();
+        run_visual(program);
     }
 }
 
-fn run_vm(cli: &Cli) {
-    let code = load_code(cli.target.as_str());
+#[cfg(feature = "visual")]
+fn run_visual(program: Program) {
+    visual::run(program.code);
+}
 
-    let mut fvm = funge::Vm::new(code);
+#[cfg(not(feature = "visual"))]
+fn run_visual(_: Program) {
+    eprintln!("Error: -v needs a build with the visual feature");
+    process::exit(EXIT_USAGE);
+}
 
-    let ran_for = match fvm.run_for(cli.stop_after) {
-        Ok(x) => x,
-        Err(_) => 0,
+fn run_vm(cli: &Cli, program: Program) {
+    let mut builder = funge::VmBuilder::new()
+        .source(program.code)
+        .dialect(dialect(cli.befunge93))
+        .base_dir(program.base_dir)
+        .limits(funge::Limits {
+            max_stack: cli.max_stack,
+            max_cells: Some(cli.max_cells).filter(|max| *max > 0),
+            max_output: cli.max_output,
+            max_time: cli.max_time.map(Duration::from_secs),
+        })
+        .detect_loops(cli.detect_loops)
+        .coverage(cli.coverage.is_some())
+        .overflow(match cli.overflow {
+            Overflow::Wrap => funge::Overflow::Wrap,
+            Overflow::Saturate => funge::Overflow::Saturate,
+            Overflow::Trap => funge::Overflow::Trap,
+        });
+    if !cli.allow_exec.is_empty() {
+        let mut policy = funge::ExecPolicy::new(cli.allow_exec.clone());
+        policy.working_dir = cli.exec_dir.clone();
+        policy.timeout = cli.exec_timeout.map(Duration::from_secs);
+        builder = builder.exec_policy(policy);
+    }
+    match (cli.cells, cli.cell_bits.as_deref()) {
+        (_, Some("8")) => run_built(cli, builder.build_with_cells::<i8>()),
+        (_, Some("16")) => run_built(cli, builder.build_with_cells::<i16>()),
+        (_, Some("32")) => run_built(cli, builder.build_with_cells::<i32>()),
+        (_, Some("64")) => run_built(cli, builder.build_with_cells::<i64>()),
+        (_, Some(_)) => run_built(cli, builder.build_with_cells::<i128>()),
+        (Cells::Fixed, None) => run_built(cli, builder.build()),
+        (Cells::Bigint, None) => run_built(cli, builder.build_with_cells::<BigInt>()),
+    }
+}
+
+fn run_built<C: funge::Cell>(cli: &Cli, mut fvm: funge::Vm<C>) {
+    let result = fvm.run(cli.stop_after);
+    if let (Some(path), Some(recorded)) = (&cli.coverage, fvm.coverage()) {
+        let name = match cli.files.is_empty() {
+            true => "-e".to_string(),
+            false => cli.files.join(" "),
+        };
+        if let Err(err) = save_coverage(path, &[(name, recorded.clone())]) {
+            eprintln!(
+                "Error: couldn't save coverage to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            if let funge::VmError::Looping(cycle) = &err {
+                let cells: Vec<String> = cycle
+                    .cells
+                    .iter()
+                    .map(|(x, y)| format!("({}, {})", x, y))
+                    .collect();
+                eprintln!("Loop runs through {}", cells.join(" "));
+            }
+            process::exit(exit_code(&err));
+        }
     };
-    println!("\nRan for {}", ran_for);
+
+    if cli.stats {
+        match outcome {
+            funge::Outcome::Stopped { .. } => eprintln!("Stopped"),
+            funge::Outcome::Quit { status, .. } => eprintln!("Quit with status {}", status),
+            funge::Outcome::OutOfTicks { .. } => eprintln!("Ran out of ticks"),
+        }
+        eprintln!("Ran for {}", outcome.ticks());
+        eprintln!("Stack depth {}", fvm.stack.as_slice().len());
+    }
+
+    match outcome {
+        funge::Outcome::Stopped { .. } => (),
+        funge::Outcome::Quit { status, .. } => process::exit(status),
+        funge::Outcome::OutOfTicks { .. } => process::exit(EXIT_OUT_OF_TICKS),
+    }
 }
 
-fn load_code(path: &str) -> String {
-    fs::read_to_string(path).expect("Error loading code")
+// runs the conformance cases, returning the exit status
+fn run_tests(args: &TestArgs) -> i32 {
+    let cases = match conformance::discover(&args.paths) {
+        Ok(cases) => cases,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return EXIT_NO_INPUT;
+        }
+    };
+    if cases.is_empty() {
+        eprintln!("Error: found no programs with .in, .out or .exit files to test");
+        return EXIT_NO_INPUT;
+    }
+    let options = conformance::Options {
+        seed: args.seed,
+        max_ticks: args.stop_after,
+        coverage: args.coverage.is_some(),
+        ..conformance::Options::default()
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    let mut recorded = Vec::new();
+    for case in cases.iter() {
+        let failures = match conformance::run_case(case, &options) {
+            Ok(verdict) => {
+                if let Some(coverage) = verdict.coverage {
+                    recorded.push((case.program.display().to_string(), coverage));
+                }
+                verdict.failures
+            }
+            Err(err) => vec![format!("couldn't run: {}", err)],
+        };
+
+        if failures.is_empty() {
+            println!("PASS {}", case.program.display());
+            passed += 1;
+            continue;
+        }
+
+        println!("FAIL {}", case.program.display());
+        for line in failures.iter().flat_map(|f| f.lines()) {
+            println!("    {}", line);
+        }
+        failed += 1;
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    if let Some(path) = &args.coverage {
+        if let Err(err) = save_coverage(path, &recorded) {
+            eprintln!(
+                "Error: couldn't save coverage to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+    match failed {
+        0 => 0,
+        _ => EXIT_TESTS_FAILED,
+    }
+}
+
+fn dialect(befunge93: bool) -> funge::Dialect {
+    match befunge93 {
+        true => funge::Dialect::Befunge93,
+        false => funge::Dialect::Befunge98,
+    }
+}
+
+// merges runs into the coverage report at `path`
+fn save_coverage(path: &Path, runs: &[(String, coverage::Coverage)]) -> io::Result<()> {
+    let mut report = coverage::Report::load(path)?;
+    for (name, recorded) in runs {
+        report.add(name, recorded);
+    }
+    report.save(path)
+}
+
+// lints each file, returning the exit status
+fn run_lint(args: &LintArgs) -> i32 {
+    let mut found = false;
+    for path in args.files.iter() {
+        let code = match fs::read(path) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("Error: couldn't read {}: {}", path.display(), err);
+                return EXIT_NO_INPUT;
+            }
+        };
+
+        for finding in lint::lint(&code, dialect(args.befunge93)) {
+            println!("{}:{}", path.display(), finding);
+            found = true;
+        }
+    }
+
+    match found {
+        true => EXIT_LINT_FINDINGS,
+        false => 0,
+    }
+}
+
+fn run_asm(args: &AsmArgs) -> i32 {
+    let read = |path: &Path| {
+        fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))
+    };
+    let source = match read(&args.file) {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return EXIT_NO_INPUT;
+        }
+    };
+    let program = match asm::parse(&source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}:{}", args.file.display(), err);
+            return 1;
+        }
+    };
+    let assembly = asm::assemble(&program);
+
+    if !args.no_verify {
+        let input = match args.input.as_deref().map(read).transpose() {
+            Ok(input) => input.unwrap_or_default(),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return EXIT_NO_INPUT;
+            }
+        };
+        if let Err(err) = asm::verify(&program, &assembly, &input, args.stop_after) {
+            eprintln!("Error: {}", err);
+            return 1;
+        }
+    }
+
+    let written = match &args.output {
+        Some(path) => fs::write(path, &assembly.code),
+        None => {
+            print!("{}", assembly.code);
+            Ok(())
+        }
+    };
+    let written = written.and_then(|_| match &args.map {
+        Some(path) => {
+            let json = serde_json::to_string_pretty(&assembly.map)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(path, json + "\n")
+        }
+        None => Ok(()),
+    });
+    match written {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
+    }
 }
 
+fn run_encode(args: &EncodeArgs) -> i32 {
+    let dialect = dialect(args.befunge93);
+
+    for value in args.values.iter() {
+        let (code, expected) = match args.string {
+            true => {
+                let bytes: Vec<i64> = value.bytes().map(|b| b as i64).collect();
+                (encode::string(value.as_bytes(), dialect), bytes)
+            }
+            false => match value.parse() {
+                Ok(n) => (encode::number(n, dialect), vec![n]),
+                Err(_) => {
+                    eprintln!("Error: {} isn't a number", value);
+                    return 1;
+                }
+            },
+        };
+
+        if encode::evaluate(&code, dialect) != Some(expected) {
+            eprintln!("Error: {} doesn't push {:?}", code, value);
+            return 1;
+        }
+        println!("{}", code);
+    }
+    0
+}
+
+fn run_show(args: &ShowArgs) -> i32 {
+    let options = show::Options {
+        colour: !args.no_colour && io::stdout().is_terminal(),
+        rulers: args.rulers,
+        path: args.path,
+        dialect: funge::Dialect::default(),
+    };
+
+    for (i, path) in args.files.iter().enumerate() {
+        let code = match fs::read(path) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("Error: couldn't read {}: {}", path.display(), err);
+                return EXIT_NO_INPUT;
+            }
+        };
+
+        if args.files.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("{}", path.display());
+        }
+        print!("{}", show::render(&code, &options));
+    }
+    0
+}
+
+// a program put together from the command line sources
+struct Program {
+    code: Vec<u8>,
+    base_dir: PathBuf, // `i` and `o` resolve file names relative to this
+}
+
+fn load_program(cli: &Cli) -> Result<Program, String> {
+    let mut sources: Vec<Vec<u8>> = cli.eval.iter().map(|e| e.clone().into_bytes()).collect();
+    for path in cli.files.iter() {
+        let source = match path.as_str() {
+            "-" => {
+                let mut buf = Vec::new();
+                io::stdin().read_to_end(&mut buf).map(|_| buf)
+            }
+            _ => fs::read(path),
+        };
+        sources.push(source.map_err(|err| format!("couldn't read {}: {}", path, err))?);
+    }
+
+    // Each source starts on a fresh line. One that ends in a lone `\r` gets
+    // a `\n` too, so a `\n` starting the next can't pair up with it.
+    let mut code: Vec<u8> = Vec::new();
+    for source in sources {
+        if !code.is_empty() && !code.ends_with(b"\n") {
+            code.push(b'\n');
+        }
+        code.extend(source);
+    }
+
+    let base_dir = cli
+        .files
+        .iter()
+        .find(|path| *path != "-")
+        .and_then(|path| Path::new(path).parent())
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+
+    Ok(Program { code, base_dir })
+}
+
+#[cfg(feature = "visual")]
 mod visual {
-    use crate::load_code;
     use core::ops::Add;
     use nannou::geom::Vec2;
     use nannou::prelude::*;
+    use std::sync::OnceLock;
     use std::{thread, time};
 
     use super::funge;
@@ -185,9 +786,14 @@ mod visual {
         draw.to_frame(app, &frame).unwrap();
     }
 
-    pub fn run() {
+    // nannou wants a plain fn to build the model, so the program is passed through here
+    static SOURCE: OnceLock<Vec<u8>> = OnceLock::new();
+
+    pub fn run(code: Vec<u8>) {
+        SOURCE.get_or_init(|| code);
+
         let model2 = |app: &App| -> Model {
-            let vm = funge::Vm::new(load_code("./test.b98"));
+            let vm = funge::Vm::new(SOURCE.get().unwrap());
             let c_rect = Rect::new(&vm);
 
             let _window = app
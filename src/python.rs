@@ -0,0 +1,377 @@
+//! Python bindings, behind the `python` feature.
+//!
+//! `maturin develop` or `maturin build` builds the extension module with
+//! the features `pyproject.toml` asks for. It holds one class, `Vm`, and
+//! the `BefungeError` it raises when a program breaks one of its limits:
+//!
+//! ```python
+//! from rsbefunge import Vm
+//!
+//! vm = Vm("&&+.@", seed=0, max_stack=1000)
+//! vm.feed("12 30")
+//! vm.run(max_ticks=1000)   # 5
+//! vm.read_output()         # b"42 "
+//! vm.stack, vm.get(0, 0)   # ([], 38)
+//! ```
+//!
+//! Input is whatever has been fed so far, and running out of it is the end
+//! of input, so `&` and `~` reflect until more is fed. Cells are signed
+//! going in and out.
+
+use crate::funge::{self, Dialect, Direction, Limits, Location, SharedBuffer, VmBuilder};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use std::cell::RefCell;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+use std::time::Duration;
+
+create_exception!(rsbefunge, BefungeError, PyRuntimeError);
+
+// how many ticks `run` goes between checking for signals
+const CHUNK: usize = 10_000;
+
+// A program and everything it was started with, so that `load` can start
+// another the same way. Python only ever sees it from the thread that made
+// it, hence `unsendable`.
+#[pyclass(unsendable, module = "rsbefunge")]
+pub struct Vm {
+    vm: funge::Vm,
+    dialect: Dialect,
+    seed: Option<u64>,
+    limits: Limits,
+    input: Rc<RefCell<Vec<u8>>>, // fed but not yet read
+    output: SharedBuffer,
+    stopped: bool,
+}
+
+#[pymethods]
+impl Vm {
+    #[new]
+    #[pyo3(signature = (
+        source = "",
+        *,
+        befunge93 = false,
+        seed = None,
+        max_stack = None,
+        max_cells = Some(Limits::DEFAULT_MAX_CELLS), // None for no limit
+        max_output = None,
+        max_time = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        source: &str,
+        befunge93: bool,
+        seed: Option<u64>,
+        max_stack: Option<usize>,
+        max_cells: Option<usize>,
+        max_output: Option<usize>,
+        max_time: Option<f64>, // seconds from the first step
+    ) -> PyResult<Vm> {
+        let max_time = max_time
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|err| PyValueError::new_err(format!("max_time: {}", err)))?;
+        let mut vm = Vm {
+            vm: funge::Vm::new(""),
+            dialect: match befunge93 {
+                true => Dialect::Befunge93,
+                false => Dialect::Befunge98,
+            },
+            seed,
+            limits: Limits {
+                max_stack,
+                max_cells,
+                max_output,
+                max_time,
+            },
+            input: Rc::default(),
+            output: SharedBuffer::new(),
+            stopped: false,
+        };
+        vm.load(source);
+        Ok(vm)
+    }
+
+    // starts `source` from the top, keeping the settings, unread input and
+    // unread output
+    fn load(&mut self, source: &str) {
+        let mut builder = VmBuilder::new()
+            .source(source)
+            .dialect(self.dialect)
+            .limits(self.limits.clone())
+            .input(Feed::new(self.input.clone()))
+            .output(self.output.clone());
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        self.vm = builder.build();
+        self.stopped = false;
+    }
+
+    // runs one instruction, returning whether the program has stopped
+    fn step(&mut self) -> PyResult<bool> {
+        self.stopped = self.vm.tick().map_err(error)?;
+        Ok(self.stopped)
+    }
+
+    // Runs until the program stops or `max_ticks` have run, returning how
+    // many did. It runs a chunk of ticks at a time, and in between lets
+    // Ctrl-C through and other Python threads have the GIL.
+    #[pyo3(signature = (max_ticks = None))]
+    fn run(&mut self, py: Python<'_>, max_ticks: Option<usize>) -> PyResult<usize> {
+        let mut ran = 0;
+        while max_ticks != Some(ran) {
+            let chunk = max_ticks.map_or(CHUNK, |max| (max - ran).min(CHUNK));
+            let outcome = self.vm.run(chunk).map_err(error)?;
+            ran += outcome.ticks();
+            if !matches!(outcome, funge::Outcome::OutOfTicks { .. }) {
+                self.stopped = true;
+                return Ok(ran);
+            }
+            self.stopped = false;
+
+            py.check_signals()?;
+            py.allow_threads(|| ());
+        }
+        Ok(ran)
+    }
+
+    // adds a str or bytes to the input
+    fn feed(&mut self, data: &Bound<'_, PyAny>) -> PyResult<()> {
+        let bytes = if let Ok(text) = data.downcast::<PyString>() {
+            text.to_str()?.as_bytes().to_vec()
+        } else if let Ok(bytes) = data.downcast::<PyBytes>() {
+            bytes.as_bytes().to_vec()
+        } else {
+            return Err(PyTypeError::new_err("feed takes str or bytes"));
+        };
+        self.input.borrow_mut().extend(bytes);
+        Ok(())
+    }
+
+    // everything written since the last call
+    fn read_output<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.output.take())
+    }
+
+    // from the bottom up
+    #[getter]
+    fn stack(&self) -> Vec<i64> {
+        self.vm
+            .stack
+            .as_slice()
+            .iter()
+            .map(|&v| funge::signed(v))
+            .collect()
+    }
+
+    fn get(&self, x: i64, y: i64) -> i64 {
+        funge::signed(self.vm.space.get(&Location(x, y)))
+    }
+
+    // anywhere may be written, outside the program too, as `p` does
+    fn put(&mut self, x: i64, y: i64, value: i64) -> PyResult<()> {
+        self.vm
+            .space
+            .set(funge::cell(value), Location(x, y))
+            .map_err(error)
+    }
+
+    // (width, height) of the program, however far away cells are put
+    #[getter]
+    fn size(&self) -> (usize, usize) {
+        self.vm.space.dims()
+    }
+
+    // (x, y)
+    #[getter]
+    fn location(&self) -> (i64, i64) {
+        let Location(x, y) = self.vm.get_location();
+        (x, y)
+    }
+
+    // "north", "south", "east" or "west"
+    #[getter]
+    fn delta(&self) -> &'static str {
+        match self.vm.get_delta() {
+            Direction::North => "north",
+            Direction::South => "south",
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+    }
+
+    #[getter]
+    fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // what `q` popped, None unless the program quit that way
+    #[getter]
+    fn exit_status(&self) -> Option<i32> {
+        self.vm.exit_status()
+    }
+
+    fn __repr__(&self) -> String {
+        let (x, y) = self.location();
+        format!(
+            "<Vm at ({}, {}) heading {}, {} on the stack{}>",
+            x,
+            y,
+            self.delta(),
+            self.vm.stack.as_slice().len(),
+            if self.stopped { ", stopped" } else { "" }
+        )
+    }
+}
+
+fn error(err: funge::VmError) -> PyErr {
+    BefungeError::new_err(err.to_string())
+}
+
+#[pymodule]
+fn rsbefunge(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Vm>()?;
+    m.add("BefungeError", m.py().get_type::<BefungeError>())?;
+    Ok(())
+}
+
+// Reads what has been fed so far, taking it all into its own buffer
+// whenever that runs dry, and handing back what's left when it's dropped
+struct Feed {
+    fed: Rc<RefCell<Vec<u8>>>,
+    buffer: Vec<u8>,
+    read: usize,
+}
+
+impl Feed {
+    fn new(fed: Rc<RefCell<Vec<u8>>>) -> Feed {
+        Feed {
+            fed,
+            buffer: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Feed {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.read == self.buffer.len() {
+            self.buffer = self.fed.take();
+            self.read = 0;
+        }
+        Ok(&self.buffer[self.read..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read = (self.read + amt).min(self.buffer.len());
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        let rest = self.buffer.split_off(self.read);
+        self.fed.borrow_mut().splice(0..0, rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    // runs `script` with the module's contents in scope
+    fn python(script: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Vm", py.get_type::<Vm>()).unwrap();
+            globals
+                .set_item("BefungeError", py.get_type::<BefungeError>())
+                .unwrap();
+            let script = CString::new(script).unwrap();
+            if let Err(err) = py.run(&script, Some(&globals), None) {
+                err.display(py);
+                panic!("{}", err);
+            }
+        });
+    }
+
+    #[test]
+    fn runs_with_fed_input() {
+        python(
+            r#"
+vm = Vm("&&+.@", seed=0)
+vm.feed("12 ")
+assert vm.run(max_ticks=1) == 1 and not vm.stopped
+assert vm.stack == [12], vm.stack
+vm.feed(b"30")
+assert vm.run() == 4 and vm.stopped
+assert vm.read_output() == b"42 "
+assert vm.read_output() == b""
+"#,
+        );
+    }
+
+    #[test]
+    fn runs_past_a_chunk() {
+        python(
+            r#"
+vm = Vm(">")
+assert vm.run(max_ticks=25_000) == 25_000 and not vm.stopped
+vm = Vm("9:*:*>1-:v\n     ^   _@")
+assert vm.run() > 25_000 and vm.stopped
+"#,
+        );
+    }
+
+    #[test]
+    fn steps_and_inspects_space() {
+        python(
+            r#"
+vm = Vm("1-v\n  @")
+assert vm.size == (3, 2)
+assert not vm.step() and not vm.step()
+assert vm.stack == [-1] and vm.location == (2, 0)
+vm.step()
+assert vm.delta == "south" and vm.location == (2, 1)
+vm.put(4, 3, -7)
+assert vm.get(4, 3) == -7 and vm.size == (3, 2)
+assert vm.get(2, 1) == ord("@")
+assert vm.step() and vm.stopped
+vm.feed("5 6")
+vm.load("&q")
+assert vm.run(10) == 2 and vm.exit_status == 5
+"#,
+        );
+    }
+
+    #[test]
+    fn raises_when_a_limit_is_broken() {
+        python(
+            r#"
+vm = Vm("1", max_stack=10)
+try:
+    vm.run(100)
+except BefungeError as err:
+    assert "stack exceeded 10 cells" in str(err), err
+else:
+    raise AssertionError("no error")
+"#,
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Pretty-prints Befunge sources for the terminal.
+//!
+//! [`render`] colours each cell by what its instruction is for, the same
+//! split the language server uses for semantic tokens, and can add rulers
+//! numbering the rows and columns. With `path` it also overlays the IP's
+//! path as [`lint::trace`] finds it: blank cells the IP crosses get an
+//! arrow for the way it goes, or `+` where it goes more than one way, and
+//! cells no path reaches are dimmed.
+
+use crate::funge::code::{Category, Instruction};
+use crate::funge::{Dialect, Direction, Location, Space};
+use crate::lint::{self, Trace};
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub colour: bool, // ANSI colours, otherwise plain text
+    pub rulers: bool,
+    pub path: bool,
+    pub dialect: Dialect,
+}
+
+const RESET: &str = "\x1b[0m";
+
+// the ANSI style of a cell, None for plain
+fn style(category: Option<Category>, string: bool) -> Option<&'static str> {
+    if string {
+        return Some("32"); // green
+    }
+    match category? {
+        Category::Literal => Some("36"),                        // cyan
+        Category::Movement => Some("1;33"),                     // bold yellow
+        Category::Flow => Some("1"),                            // bold
+        Category::Arithmetic => Some("35"),                     // magenta
+        Category::Stack | Category::Memory => Some("31"),       // red
+        Category::Io => Some("34"),                             // blue
+        Category::System | Category::Fingerprint => Some("94"), // bright blue
+    }
+}
+
+const DIM: &str = "2";
+
+// a cell as it's drawn
+struct Cell {
+    glyph: char,
+    style: Option<&'static str>,
+}
+
+pub fn render(code: &[u8], options: &Options) -> String {
+    let space = Space::<usize>::new(code);
+    let trace = lint::trace(&space, options.dialect);
+    let (w, h) = space.dims();
+
+    let margin = match options.rulers {
+        true => (h.max(1) - 1).to_string().len() + 1,
+        false => 0,
+    };
+
+    let mut out = String::new();
+    if options.rulers {
+        ruler(&mut out, w, margin);
+    }
+
+    for y in 0..h {
+        let cells: Vec<Cell> = (0..w)
+            .map(|x| cell(&space, &trace, x, y, options))
+            .collect();
+        let end = cells
+            .iter()
+            .rposition(|c| c.glyph != ' ')
+            .map_or(0, |i| i + 1);
+
+        if options.rulers {
+            write!(out, "{:>width$}", y, width = margin - 1).ok();
+            if end > 0 {
+                out.push(' ');
+            }
+        }
+        let mut current = None;
+        for cell in cells[..end].iter() {
+            let style = cell.style.filter(|_| options.colour);
+            if style != current {
+                if current.is_some() {
+                    out.push_str(RESET);
+                }
+                if let Some(style) = style {
+                    write!(out, "\x1b[{}m", style).ok();
+                }
+                current = style;
+            }
+            out.push(cell.glyph);
+        }
+        if current.is_some() {
+            out.push_str(RESET);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn cell(space: &Space<usize>, trace: &Trace, x: usize, y: usize, options: &Options) -> Cell {
+    let raw = space.get(&Location(x as i64, y as i64));
+    let glyph = match u8::try_from(raw) {
+        Ok(byte) if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+        _ => '?',
+    };
+    let visit = trace.visit(x, y);
+
+    if glyph == ' ' {
+        let headings: Vec<char> = [
+            (Direction::East, '→'),
+            (Direction::West, '←'),
+            (Direction::North, '↑'),
+            (Direction::South, '↓'),
+        ]
+        .iter()
+        .filter(|(d, _)| visit.heading(d))
+        .map(|(_, arrow)| *arrow)
+        .collect();
+        return match (options.path && visit.code, headings.as_slice()) {
+            (true, [arrow]) => Cell {
+                glyph: *arrow,
+                style: Some(DIM),
+            },
+            (true, [_, ..]) => Cell {
+                glyph: '+',
+                style: Some(DIM),
+            },
+            _ => Cell { glyph, style: None },
+        };
+    }
+
+    if options.path && !visit.reached() {
+        return Cell {
+            glyph,
+            style: Some(DIM),
+        };
+    }
+
+    let string = raw == b'"' as usize || (visit.string && !visit.code);
+    let instruction = Instruction::from_raw(raw, &false, &options.dialect);
+    let category = match instruction {
+        Instruction::NoOp => None,
+        Instruction::Reflect if raw != b'r' as usize => None,
+        _ => Some(instruction.category()),
+    };
+    Cell {
+        glyph,
+        style: style(category, string),
+    }
+}
+
+// column numbers, tens above units
+fn ruler(out: &mut String, width: usize, margin: usize) {
+    let tens: String = (0..width)
+        .map(|x| match x % 10 {
+            0 if x > 0 => char::from_digit((x / 10 % 10) as u32, 10).unwrap(),
+            _ => ' ',
+        })
+        .collect();
+    let units: String = (0..width)
+        .map(|x| char::from_digit((x % 10) as u32, 10).unwrap())
+        .collect();
+
+    if width > 10 {
+        writeln!(out, "{:margin$}{}", "", tens.trim_end(), margin = margin).ok();
+    }
+    writeln!(out, "{:margin$}{}", "", units, margin = margin).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(code: &str, rulers: bool, path: bool) -> String {
+        let options = Options {
+            rulers,
+            path,
+            ..Options::default()
+        };
+        render(code.as_bytes(), &options)
+    }
+
+    #[test]
+    fn colours_by_category() {
+        let options = Options {
+            colour: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            render(b">\"a\"1+.@", &options),
+            "\x1b[1;33m>\x1b[0m\x1b[32m\"a\"\x1b[0m\x1b[36m1\x1b[0m\x1b[35m+\x1b[0m\
+             \x1b[34m.\x1b[0m\x1b[1m@\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn rulers_number_rows_and_columns() {
+        assert_eq!(
+            plain("12345678901@\n\n\n\n\n\n\n\n\n\nv", true, false),
+            "             1\n   012345678901\n 0 12345678901@\n 1\n 2\n 3\n 4\n 5\n 6\n 7\n 8\n 9\n10 v\n"
+        );
+    }
+
+    #[test]
+    fn path_overlays_arrows() {
+        assert_eq!(plain(">  v\n@  <", false, true), ">→→v\n@←←<\n");
+        assert_eq!(plain("#v  @\n x", false, true), "#v→→@\n x\n");
+    }
+}
@@ -0,0 +1,237 @@
+//! JavaScript bindings for running programs in a browser, behind the `wasm`
+//! feature.
+//!
+//! [`WasmVm`] wraps a [`Vm`] for `wasm-bindgen`, so a playground can step
+//! through a program and draw its stack and funge space as it goes:
+//!
+//! ```js
+//! import init, { WasmVm } from "./pkg/rsbefunge.js";
+//!
+//! await init();
+//! const decoder = new TextDecoder();
+//! const vm = new WasmVm('"!iH",,,@', {
+//!     output: (bytes) => console.log(decoder.decode(bytes, { stream: true })),
+//!     input: () => prompt("input") ?? undefined,
+//! });
+//! while (!vm.runFor(1000)) { /* redraw */ }
+//! ```
+//!
+//! Output is handed to the `output` callback as a `Uint8Array` whenever the
+//! program writes. The `input` callback is called whenever `&` or `~` needs
+//! more input and returns a string, a `Uint8Array`, or `undefined` or `null`
+//! at end of input. Either can be left out, to drop output or to run with
+//! no input.
+//!
+//! Cells are as wide as `usize`, so 32 bits on wasm32. There is no system
+//! clock to limit the run time with and no processes for `=` to run, so the
+//! only limits are the ticks handed to `tick` and `runFor`.
+//!
+//! Build it with
+//! `wasm-pack build --target web --no-default-features --features wasm` and
+//! test it with `wasm-pack test --node --no-default-features --features wasm`,
+//! which runs `tests/wasm.rs` under node.
+
+use crate::funge::{Dialect, Direction, Location, Vm, VmBuilder};
+use js_sys::{Function, Reflect, Uint8Array};
+use std::io::{self, BufRead, Read, Write};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WasmVm {
+    vm: Vm,
+}
+
+#[wasm_bindgen]
+impl WasmVm {
+    // `options` is an object with any of `output`, `input`, `seed` and
+    // `befunge93`
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, options: Option<js_sys::Object>) -> Result<WasmVm, JsError> {
+        let option = |name: &str| match &options {
+            Some(options) => Reflect::get(options, &JsValue::from_str(name))
+                .ok()
+                .filter(|value| !value.is_undefined() && !value.is_null()),
+            None => None,
+        };
+        let callback = |name: &str| -> Result<Option<Function>, JsError> {
+            option(name)
+                .map(|value| value.dyn_into::<Function>())
+                .transpose()
+                .map_err(|_| JsError::new(&format!("{} must be a function", name)))
+        };
+
+        let mut builder = VmBuilder::new()
+            .source(source)
+            .input(Input::new(callback("input")?))
+            .output(Output(callback("output")?));
+        if let Some(seed) = option("seed").and_then(|seed| seed.as_f64()) {
+            builder = builder.seed(seed as u64);
+        }
+        if option("befunge93").is_some_and(|flag| flag.is_truthy()) {
+            builder = builder.dialect(Dialect::Befunge93);
+        }
+        Ok(WasmVm {
+            vm: builder.build(),
+        })
+    }
+
+    // runs one instruction, true once the program has stopped
+    pub fn tick(&mut self) -> Result<bool, JsError> {
+        self.vm.tick().map_err(error)
+    }
+
+    // runs up to `ticks` instructions, true once the program has stopped
+    #[wasm_bindgen(js_name = runFor)]
+    pub fn run_for(&mut self, ticks: usize) -> Result<bool, JsError> {
+        for _ in 0..ticks {
+            if self.tick()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // the stack from the bottom up, as signed numbers
+    pub fn stack(&self) -> Vec<i32> {
+        self.vm.stack.as_slice().iter().map(|&v| v as i32).collect()
+    }
+
+    pub fn width(&self) -> usize {
+        self.vm.space.dims().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.vm.space.dims().1
+    }
+
+    pub fn cell(&self, x: i32, y: i32) -> i32 {
+        self.vm.space.get(&Location(x as i64, y as i64)) as i32
+    }
+
+    // a row of funge space as text, cells that aren't characters shown as `?`
+    pub fn row(&self, y: i32) -> String {
+        (0..self.width() as i32)
+            .map(|x| char::from_u32(self.cell(x, y) as u32).unwrap_or('?'))
+            .collect()
+    }
+
+    // the IP's position as [x, y]
+    pub fn location(&self) -> Vec<i32> {
+        let Location(x, y) = self.vm.get_location();
+        vec![x as i32, y as i32]
+    }
+
+    // the way the IP is heading: "north", "south", "east" or "west"
+    pub fn delta(&self) -> String {
+        match self.vm.get_delta() {
+            Direction::North => "north",
+            Direction::South => "south",
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+        .to_string()
+    }
+
+    #[wasm_bindgen(js_name = stringMode)]
+    pub fn string_mode(&self) -> bool {
+        self.vm.get_string_mode()
+    }
+
+    // the status popped by `q`, undefined unless the program quit that way
+    #[wasm_bindgen(js_name = exitStatus)]
+    pub fn exit_status(&self) -> Option<i32> {
+        self.vm.exit_status()
+    }
+}
+
+fn error(err: impl std::fmt::Display) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+fn js_error(value: JsValue) -> io::Error {
+    let message = value
+        .as_string()
+        .or_else(|| {
+            value
+                .dyn_ref::<js_sys::Error>()
+                .map(|err| String::from(err.message()))
+        })
+        .unwrap_or_else(|| "callback failed".to_string());
+    io::Error::other(message)
+}
+
+// hands everything written to a JS callback
+struct Output(Option<Function>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(callback) = &self.0 {
+            callback
+                .call1(&JsValue::NULL, &Uint8Array::from(buf))
+                .map_err(js_error)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// reads from a JS callback, asking it for more whenever what it last gave
+// runs out. An empty string is the end of input for that read only
+struct Input {
+    callback: Option<Function>,
+    buffer: Vec<u8>,
+    read: usize,
+}
+
+impl Input {
+    fn new(callback: Option<Function>) -> Input {
+        Input {
+            callback,
+            buffer: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.read == self.buffer.len() {
+            let Some(callback) = &self.callback else {
+                return Ok(&[]);
+            };
+            let value = callback.call0(&JsValue::NULL).map_err(js_error)?;
+            self.buffer = if let Some(text) = value.as_string() {
+                text.into_bytes()
+            } else if let Some(bytes) = value.dyn_ref::<Uint8Array>() {
+                bytes.to_vec()
+            } else if value.is_undefined() || value.is_null() {
+                self.callback = None;
+                Vec::new()
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "input must be a string or a Uint8Array",
+                ));
+            };
+            self.read = 0;
+        }
+        Ok(&self.buffer[self.read..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read = (self.read + amt).min(self.buffer.len());
+    }
+}
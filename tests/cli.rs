@@ -0,0 +1,97 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+// Runs the command line interpreter and checks the statuses it exits with.
+
+use std::fs;
+use std::process::Command;
+
+fn status(args: &[&str]) -> i32 {
+    let output = Command::new(env!("CARGO_BIN_EXE_rsbefunge"))
+        .args(args)
+        .output()
+        .unwrap();
+    output.status.code().unwrap()
+}
+
+#[test]
+fn quitting_exits_with_the_popped_status() {
+    assert_eq!(status(&["-e", "@"]), 0);
+    assert_eq!(status(&["-e", "3q"]), 3);
+    assert_eq!(status(&["-e", "7q"]), 7);
+}
+
+#[test]
+fn runs_until_the_program_stops() {
+    // well over a hundred ticks
+    assert_eq!(status(&["-e", "88*8*>1-:v", "-e", "     ^   _@"]), 0);
+}
+
+#[test]
+fn errors_have_statuses_of_their_own() {
+    assert_eq!(status(&["-s", "10", "-e", ">"]), 240);
+    assert_eq!(status(&["--max-stack", "4", "-e", ">1"]), 241);
+    assert_eq!(status(&["--detect-loops", "-e", ">"]), 245);
+    assert_eq!(status(&["--max-cells", "3", "-e", "\"X\"99*0p@"]), 242);
+    assert_eq!(status(&["--max-cells", "0", "-e", "\"X\"99*0p@"]), 0);
+    assert_eq!(
+        status(&["--cell-bits", "8", "--overflow", "trap", "-e", "88*88**@"]),
+        246
+    );
+
+    // nothing stops a program picking one of them itself
+    assert_eq!(status(&["-e", "f8*f8*1++q"]), 241);
+}
+
+#[test]
+fn befunge93_is_a_flag_away() {
+    // `a` and `q` are instructions in Funge-98, but in Befunge-93 they push
+    // their characters and the program never ends
+    assert_eq!(status(&["-s", "10", "-e", "aq"]), 10);
+    assert_eq!(status(&["-s", "10", "--befunge93", "-e", "aq"]), 240);
+}
+
+#[test]
+fn testing_nothing_fails() {
+    let dir = std::env::temp_dir().join(format!("rsbefunge-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lone.b98"), "@").unwrap();
+    let dir = dir.to_str().unwrap();
+
+    assert_eq!(status(&["test", dir]), 66);
+    assert_eq!(status(&["test", &format!("{}/lone.b98", dir)]), 66);
+    assert_eq!(status(&["test", &format!("{}/missing.b98", dir)]), 66);
+
+    fs::write(format!("{}/lone.out", dir), "").unwrap();
+    assert_eq!(status(&["test", dir]), 0);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn befunge93_cases_are_tested_as_befunge93() {
+    let dir = std::env::temp_dir().join(format!("rsbefunge-b93-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // `a` pushes 10 in Funge-98 but its own character in Befunge-93
+    fs::write(dir.join("old.b93"), "a.@").unwrap();
+    fs::write(dir.join("old.out"), "97 ").unwrap();
+    let dir = dir.to_str().unwrap();
+
+    assert_eq!(status(&["test", dir]), 0);
+    fs::rename(format!("{}/old.b93", dir), format!("{}/old.b98", dir)).unwrap();
+    assert_eq!(status(&["test", dir]), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn files_stack_up_line_by_line() {
+    let dir = std::env::temp_dir().join(format!("rsbefunge-join-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // the `\r` ends the first file's only line, so (1, 1) is a space
+    fs::write(dir.join("a.b98"), "11gq\r").unwrap();
+    fs::write(dir.join("b.b98"), "\n X").unwrap();
+    let (a, b) = (dir.join("a.b98"), dir.join("b.b98"));
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+    assert_eq!(status(&[a, b]), 32);
+    assert_eq!(status(&["-t", a, b]), 32);
+    fs::remove_dir_all(&dir).unwrap();
+}
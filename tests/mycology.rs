@@ -0,0 +1,76 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+// Runs the self-checking programs under tests/mycology. Each one prints a
+// GOOD or BAD line per check, and its .out file lists the GOOD lines only.
+
+use rsbefunge::conformance::{self, Case, Options};
+use std::path::{Path, PathBuf};
+
+fn suite() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mycology")
+}
+
+fn check(name: &str) {
+    let program = suite().join(format!("{}.b98", name));
+    let case = Case::for_program(&program).expect("no expectations for program");
+    let verdict = conformance::run_case(&case, &Options::default()).unwrap();
+
+    let output = String::from_utf8_lossy(&verdict.output);
+    let bad: Vec<&str> = output.lines().filter(|l| l.starts_with("BAD")).collect();
+    assert!(bad.is_empty(), "{}:\n{}", name, bad.join("\n"));
+    assert!(
+        verdict.passed(),
+        "{}:\n{}",
+        name,
+        verdict.failures.join("\n")
+    );
+}
+
+macro_rules! mycology {
+    ($($name:ident),* $(,)?) => {
+        const PROGRAMS: &[&str] = &[$(stringify!($name)),*];
+
+        $(
+            #[test]
+            fn $name() {
+                check(stringify!($name));
+            }
+        )*
+    };
+}
+
+mycology!(
+    arithmetic,
+    exec,
+    files,
+    fingerprints,
+    flow,
+    io,
+    quit,
+    space,
+    stack,
+    strings,
+    sysinfo,
+    vertical,
+    wrap,
+);
+
+#[test]
+fn every_program_has_a_test() {
+    let cases = conformance::discover(&[suite()]).unwrap();
+    let mut names: Vec<String> = cases
+        .iter()
+        .map(|c| {
+            c.program
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    names.sort();
+
+    let mut expected: Vec<&str> = PROGRAMS.to_vec();
+    expected.sort();
+    assert_eq!(names, expected);
+}
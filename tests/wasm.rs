@@ -0,0 +1,64 @@
+// Runs the JavaScript bindings under a wasm runtime, with
+// wasm-pack test --node -- --no-default-features --features wasm --test wasm
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use js_sys::{Function, Object, Reflect, Uint8Array};
+use rsbefunge::wasm::WasmVm;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// a Vm whose output ends up in the returned buffer
+fn vm(source: &str, input: Option<&Function>) -> (WasmVm, Rc<RefCell<Vec<u8>>>) {
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let sink = written.clone();
+    let output = Closure::<dyn FnMut(Uint8Array)>::new(move |bytes: Uint8Array| {
+        sink.borrow_mut().extend(bytes.to_vec())
+    });
+
+    let options = Object::new();
+    Reflect::set(&options, &"output".into(), output.as_ref()).unwrap();
+    if let Some(input) = input {
+        Reflect::set(&options, &"input".into(), input).unwrap();
+    }
+    output.forget();
+    (WasmVm::new(source, Some(options)).unwrap(), written)
+}
+
+#[wasm_bindgen_test]
+fn runs_programs_and_hands_output_to_the_callback() {
+    let (mut vm, output) = vm(r#""!iH",,,@"#, None);
+    assert!(vm.run_for(100).unwrap());
+    assert_eq!(output.borrow().as_slice(), b"Hi!");
+}
+
+#[wasm_bindgen_test]
+fn cells_wrap_at_32_bits() {
+    let (mut vm, output) = vm("01-:.@", None);
+    assert!(vm.run_for(100).unwrap());
+    assert_eq!(vm.stack(), vec![-1]);
+    assert_eq!(output.borrow().as_slice(), b"-1 ");
+}
+
+#[wasm_bindgen_test]
+fn asks_the_input_callback_for_more_when_it_runs_out() {
+    let input = Function::new_no_args(
+        "this.reads = (this.reads || 0) + 1; return ['12 ', '30'][this.reads - 1];",
+    );
+    let (mut vm, output) = vm("&&+.@", Some(&input));
+    assert!(vm.run_for(100).unwrap());
+    assert_eq!(output.borrow().as_slice(), b"42 ");
+}
+
+#[wasm_bindgen_test]
+fn exposes_the_ip_and_space() {
+    let (mut vm, _) = vm("1v\n @", None);
+    assert!(!vm.tick().unwrap());
+    assert!(!vm.tick().unwrap());
+    assert_eq!(vm.location(), vec![1, 1]);
+    assert_eq!(vm.delta(), "south");
+    assert_eq!((vm.width(), vm.height()), (2, 2));
+    assert_eq!(vm.row(0), "1v");
+    assert_eq!(vm.cell(1, 1), '@' as i32);
+}